@@ -0,0 +1,92 @@
+//! Measures lexer and parser throughput on generated multi-megabyte sources.
+//! Run with `cargo bench`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use rscript2::parser::{AstParser, Lexer};
+
+const SNIPPET: &str = r#"
+/// Sums the even numbers below `limit`.
+pub fn sum_evens(limit: int) -> int {
+    let total = 0;
+    for i in 0..limit {
+        if i % 2 == 0 {
+            total += i;
+        }
+    }
+    total
+}
+
+// Line comments and /* block comments */ are skipped
+let names = ["alpha", "beta", "gamma", "日本語"];
+let config = { width: 1_920, height: 0x438, scale: 1.5e0 };
+let message = "found {names[1]} at {config.width * 2}\n";
+let area = fn(w: int, h: int) -> int { w * h };
+while config.scale < 100.0 {
+    config.scale *= 2.0;
+}
+"#;
+
+/// A statement repeated without line breaks, as in generated or minified
+/// scripts, where every token is on one very long line.
+const LINE: &str = r#"let v = f(a, [1, 2.5, "é{b}"], { k: b * 2 }); "#;
+
+const SIZES: [usize; 3] = [1 << 20, 4 << 20, 16 << 20];
+const ITERATIONS: u32 = 5;
+
+fn generate(snippet: &str, size: usize) -> String {
+    let mut src = String::with_capacity(size + snippet.len());
+    while src.len() < size {
+        src.push_str(snippet);
+    }
+    src
+}
+
+/// Runs `run` several times over `src`, reporting the fastest time.
+fn bench(name: &str, src: &str, run: impl Fn(&str) -> usize) {
+    let mut best = Duration::MAX;
+    let mut count = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        count = black_box(run(black_box(src)));
+        best = best.min(start.elapsed());
+    }
+    let megabytes = src.len() as f64 / (1 << 20) as f64;
+    println!(
+        "{:<20} {:>6.1} MiB {:>10.2?} {:>8.1} MiB/s {:>10} items",
+        name,
+        megabytes,
+        best,
+        megabytes / best.as_secs_f64(),
+        count
+    );
+}
+
+fn main() {
+    let sources = SIZES.into_iter().flat_map(|size| {
+        [
+            ("", generate(SNIPPET, size)),
+            (" (1 line)", generate(LINE, size)),
+        ]
+    });
+    for (suffix, src) in sources {
+        bench(&format!("tokens{}", suffix), &src, |src| {
+            Lexer::new("<bench>", src)
+                .try_parse_tokens()
+                .expect("benchmark source should lex")
+                .len()
+        });
+        bench(&format!("recovering{}", suffix), &src, |src| {
+            Lexer::new("<bench>", src).parse_tokens_recovering().0.len()
+        });
+        bench(&format!("parse{}", suffix), &src, |src| {
+            AstParser::from_stream(Lexer::new("<bench>", src))
+                .parse_program()
+                .expect("benchmark source should parse")
+                .len()
+        });
+    }
+}
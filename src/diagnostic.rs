@@ -0,0 +1,420 @@
+use std::fmt::Write;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::parser::{format_alternatives, LexError, ParseError, SourceMap, Span, Str};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn style(&self) -> &'static str {
+        match self {
+            Severity::Error => BOLD_RED,
+            Severity::Warning => BOLD_YELLOW,
+        }
+    }
+}
+
+/// A span of source text to point out, along with an explanation. An empty
+/// message only underlines the span.
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: Str,
+    /// Primary labels mark the cause of the problem, while secondary labels
+    /// give context.
+    pub primary: bool,
+}
+
+/// A problem found in a program, which may be rendered along with the source
+/// it refers to.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: Str,
+    pub labels: Vec<Label>,
+    pub notes: Vec<Str>,
+    pub help: Vec<Str>,
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+
+/// Applies ANSI styles to text, unless colour is disabled.
+struct Painter {
+    color: bool,
+}
+
+impl Painter {
+    fn paint(&self, style: &str, text: impl std::fmt::Display) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// The number of terminal columns `text` occupies, with tabs expanded to four
+/// columns. Wide characters such as CJK ideographs take two columns.
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|ch| match ch {
+            '\t' => 4,
+            _ => ch.width().unwrap_or(0),
+        })
+        .sum()
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<Str>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<Str>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<Str>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message)
+    }
+
+    /// Adds a primary label. An empty message only underlines the span.
+    pub fn with_label(mut self, span: Span, message: impl Into<Str>) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    pub fn with_secondary_label(mut self, span: Span, message: impl Into<Str>) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<Str>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<Str>) -> Diagnostic {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Renders the diagnostic in the style of rustc, showing the source line
+    /// of every label. Spans which do not belong to a file in `sources` are
+    /// reported by position only.
+    pub fn render(&self, sources: &SourceMap, color: bool) -> String {
+        let painter = Painter { color };
+        let mut out = String::new();
+        let _ = self.write(&mut out, sources, &painter);
+        out
+    }
+
+    fn write(&self, out: &mut String, sources: &SourceMap, painter: &Painter) -> std::fmt::Result {
+        let style = self.severity.style();
+        writeln!(
+            out,
+            "{}{}",
+            painter.paint(style, self.severity.name()),
+            painter.paint(BOLD, format_args!(": {}", self.message))
+        )?;
+
+        // Labels are grouped by file, starting with the file of the first
+        // primary label, and shown in source order within each file
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|label| !label.primary);
+        let mut files: Vec<(&Span, Vec<&Label>)> = Vec::new();
+        for label in labels {
+            let existing = files
+                .iter_mut()
+                .find(|(span, _)| span.file == label.span.file && span.name == label.span.name);
+            match existing {
+                Some((_, file_labels)) => file_labels.push(label),
+                None => files.push((&label.span, vec![label])),
+            }
+        }
+
+        let max_row = self
+            .labels
+            .iter()
+            .map(|label| label.span.start.row + 1)
+            .max()
+            .unwrap_or(0);
+        let width = max_row.to_string().len();
+        let gutter = painter.paint(BOLD_BLUE, format_args!("{:width$} |", ""));
+
+        for (i, (header, file_labels)) in files.iter_mut().enumerate() {
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            writeln!(
+                out,
+                "{:width$}{} {}",
+                "",
+                painter.paint(BOLD_BLUE, arrow),
+                header
+            )?;
+            let file = match sources.file_of(header) {
+                Some(file) => file,
+                None => continue,
+            };
+            writeln!(out, "{}", gutter)?;
+
+            file_labels.sort_by_key(|label| (label.span.start.row, label.span.start.col));
+            let mut last_row = None;
+            for label in file_labels.iter() {
+                let row = label.span.start.row;
+                let text = match file.line_text(row) {
+                    Some(text) => text,
+                    None => continue,
+                };
+                if last_row != Some(row) {
+                    if matches!(last_row, Some(last) if last + 1 < row) {
+                        writeln!(out, "{}", painter.paint(BOLD_BLUE, "..."))?;
+                    }
+                    writeln!(
+                        out,
+                        "{} {}",
+                        painter.paint(BOLD_BLUE, format_args!("{:>width$} |", row + 1)),
+                        text.replace('\t', "    ")
+                    )?;
+                    last_row = Some(row);
+                }
+                writeln!(out, "{}", self.underline(label, text, &gutter, painter))?;
+            }
+        }
+
+        if !files.is_empty() && (!self.notes.is_empty() || !self.help.is_empty()) {
+            writeln!(out, "{}", gutter)?;
+        }
+        let notes = self.notes.iter().map(|note| ("note", note));
+        let help = self.help.iter().map(|help| ("help", help));
+        for (kind, text) in notes.chain(help) {
+            writeln!(
+                out,
+                "{:width$} {} {}: {}",
+                "",
+                painter.paint(BOLD_BLUE, "="),
+                painter.paint(BOLD, kind),
+                text
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Underlines a label's span within the line `text`. Spans running onto
+    /// later lines are underlined to the end of the first line.
+    fn underline(&self, label: &Label, text: &str, gutter: &str, painter: &Painter) -> String {
+        let start = label.span.start.col;
+        let stop = if label.span.stop.row == label.span.start.row {
+            label.span.stop.col
+        } else {
+            text.chars().count()
+        };
+        let prefix: String = text.chars().take(start).collect();
+        let marked: String = text.chars().skip(start).take(stop - start).collect();
+        let (mark, style) = if label.primary {
+            ('^', self.severity.style())
+        } else {
+            ('-', BOLD_BLUE)
+        };
+
+        let marks = mark.to_string().repeat(display_width(&marked).max(1));
+        let mut line = format!("{} {}", gutter, " ".repeat(display_width(&prefix)));
+        if label.message.is_empty() {
+            line += &painter.paint(style, marks);
+        } else {
+            line += &painter.paint(style, format_args!("{} {}", marks, label.message));
+        }
+        line
+    }
+}
+
+impl From<&LexError> for Diagnostic {
+    fn from(why: &LexError) -> Diagnostic {
+        let span = why.span().clone();
+        let diagnostic = Diagnostic::error(why.to_string());
+        match why {
+            LexError::UnterminatedComment(_) => diagnostic.with_label(span, "comment begins here"),
+            LexError::UnterminatedString(_) => diagnostic.with_label(span, "string begins here"),
+            LexError::InvalidEscape(_) => diagnostic
+                .with_label(span, "")
+                .with_help(r#"valid escapes are `\n`, `\t`, `\r`, `\0`, `\\`, `\"` and `\u{...}`"#),
+            _ => diagnostic.with_label(span, ""),
+        }
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(why: &ParseError) -> Diagnostic {
+        let span = why.span().clone();
+        match why {
+            ParseError::Lex(why) => Diagnostic::from(why),
+            ParseError::Eof { expected, .. } | ParseError::ExpectedToken { expected, .. }
+                if !expected.is_empty() =>
+            {
+                let label = format!("expected {}", format_alternatives(expected));
+                Diagnostic::error(why.to_string()).with_label(span, label)
+            }
+            ParseError::InvalidAssignment(_) => Diagnostic::error(why.to_string())
+                .with_label(span, "cannot be assigned to")
+                .with_help("only variables, indexes and members can be assigned to"),
+            _ => Diagnostic::error(why.to_string()).with_label(span, ""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{AstParser, Lexer};
+
+    fn parse_error(sources: &mut SourceMap, src: &str) -> Diagnostic {
+        let file = sources.add("test.rs2", src);
+        let why = AstParser::from_stream(Lexer::for_file(sources.get(file)))
+            .parse_program()
+            .expect_err("source should fail to parse");
+        Diagnostic::from(&why)
+    }
+
+    #[test]
+    fn renders_snippet() {
+        let mut sources = SourceMap::new();
+        let diagnostic = parse_error(&mut sources, "let x = 1;\nlet y = x z;\n");
+        assert_eq!(
+            diagnostic.render(&sources, false),
+            "\
+error: expected `;`, found identifier `z`
+ --> test.rs2:2:11
+  |
+2 | let y = x z;
+  |           ^ expected `;`
+"
+        );
+    }
+
+    #[test]
+    fn renders_lex_errors() {
+        let mut sources = SourceMap::new();
+        let diagnostic = parse_error(&mut sources, "let s = \"a\\qb\";");
+        assert_eq!(
+            diagnostic.render(&sources, false),
+            "\
+error: invalid escape sequence
+ --> test.rs2:1:11
+  |
+1 | let s = \"a\\qb\";
+  |           ^^
+  |
+  = help: valid escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"` and `\\u{...}`
+"
+        );
+    }
+
+    #[test]
+    fn renders_labels_and_notes() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("a.rs2", "let a = 1;\n\tlet a = 2;");
+        let second = sources.add("b.rs2", "a = 3;");
+        let tokens = |id| Lexer::for_file(sources.get(id)).try_parse_tokens().unwrap();
+        let first_tokens = tokens(file);
+        let second_tokens = tokens(second);
+
+        let diagnostic = Diagnostic::warning("`a` is declared twice")
+            .with_label(first_tokens[6].span.clone(), "redeclared here")
+            .with_secondary_label(first_tokens[1].span.clone(), "first declared here")
+            .with_secondary_label(second_tokens[0].span.clone(), "used here")
+            .with_note("the first declaration is shadowed");
+        assert_eq!(
+            diagnostic.render(&sources, false),
+            "\
+warning: `a` is declared twice
+ --> a.rs2:2:6
+  |
+1 | let a = 1;
+  |     - first declared here
+2 |     let a = 2;
+  |         ^ redeclared here
+ ::: b.rs2:1:1
+  |
+1 | a = 3;
+  | - used here
+  |
+  = note: the first declaration is shadowed
+"
+        );
+    }
+
+    #[test]
+    fn colour_can_be_disabled() {
+        let mut sources = SourceMap::new();
+        let diagnostic = parse_error(&mut sources, "let = 1;");
+        assert!(diagnostic.render(&sources, true).contains(BOLD_RED));
+        assert!(!diagnostic.render(&sources, false).contains('\x1b'));
+    }
+
+    #[test]
+    fn spans_outside_source_map() {
+        let tokens = Lexer::new("<repl>", "x").try_parse_tokens().unwrap();
+        let diagnostic = Diagnostic::error("oops").with_label(tokens[0].span.clone(), "");
+        assert_eq!(
+            diagnostic.render(&SourceMap::new(), false),
+            "error: oops\n --> <repl>:1:1\n"
+        );
+    }
+
+    #[test]
+    fn wide_characters() {
+        let mut sources = SourceMap::new();
+        let diagnostic = parse_error(&mut sources, "let 名前 = @;");
+        assert_eq!(
+            diagnostic.render(&sources, false),
+            "\
+error: unknown symbol `@`
+ --> test.rs2:1:10
+  |
+1 | let 名前 = @;
+  |            ^
+"
+        );
+
+        let mut sources = SourceMap::new();
+        let diagnostic = parse_error(&mut sources, "let x = 变量 变量;");
+        assert_eq!(
+            diagnostic.render(&sources, false),
+            "\
+error: expected `;`, found identifier `变量`
+ --> test.rs2:1:12
+  |
+1 | let x = 变量 变量;
+  |              ^^^^ expected `;`
+"
+        );
+    }
+}
@@ -1,13 +1,23 @@
-#![allow(dead_code)]
+use std::io::IsTerminal;
 
-mod parser;
+use rscript2::{
+    diagnostic::Diagnostic,
+    parser::{AstParser, Lexer, SourceMap},
+};
 
 const SOURCE: &str = include_str!("../test.txt");
 
-use crate::parser::Lexer;
-
 fn main() {
-    let mut lexer = Lexer::new("<stdin>", SOURCE);
-    let res = lexer.try_parse_tokens();
-    println!("{:#?}", res);
+    let mut sources = SourceMap::new();
+    let file = sources.add("<stdin>", SOURCE);
+
+    let mut parser = AstParser::from_stream(Lexer::for_file(sources.get(file)));
+    match parser.parse_program() {
+        Ok(program) => println!("{:#?}", program),
+        Err(why) => {
+            // Colour is left out of logs, and can be disabled with NO_COLOR
+            let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            eprint!("{}", Diagnostic::from(&why).render(&sources, color));
+        }
+    }
 }
@@ -1,28 +1,141 @@
-use crate::parser::{Expression, ParseError, ParseResult, Span, SpanData, Statement, Token};
+use std::collections::VecDeque;
 
-pub struct AstParser {
-    tokens: Vec<SpanData<Token>>,
+use crate::parser::{
+    Assign, Binary, BinaryOperator, Block, Call, Declaration, Expression, For, Function,
+    FunctionType, GenericType, If, Index, InterpolationSegment, Lambda, LexError, LexResult, Loop,
+    MapEntry, Member, ParseError, ParseResult, Point, Range, Span, SpanData, Statement, Str,
+    StringSegment, Token, TypeExpression, Typed, Unary, UnaryOperator, Visibility, While,
+};
+
+type TokenStream<'a> = Box<dyn Iterator<Item = LexResult<SpanData<Token>>> + 'a>;
+
+pub struct AstParser<'a> {
+    source: TokenStream<'a>,
+    /// Tokens pulled from `source` which may still be needed for backtracking.
+    tokens: VecDeque<SpanData<Token>>,
+    /// The index of the first token in `tokens`, counting from the start of
+    /// the stream.
+    base: usize,
     index: usize,
+    /// The number of enclosing `try_run` calls which may backtrack.
+    checkpoints: usize,
+    /// The error which cut the token stream short, if any.
+    lex_error: Option<LexError>,
+    last_span: Option<Span>,
+    loop_depth: usize,
 }
 
-impl AstParser {
-    pub fn new(tokens: Vec<SpanData<Token>>) -> AstParser {
-        AstParser { tokens, index: 0 }
+impl AstParser<'static> {
+    pub fn new(tokens: Vec<SpanData<Token>>) -> AstParser<'static> {
+        AstParser::from_stream(tokens.into_iter().map(Ok))
+    }
+}
+
+impl<'a> AstParser<'a> {
+    /// Creates a parser which pulls tokens from `tokens` as they are needed,
+    /// such as a `Lexer`.
+    pub fn from_stream(
+        tokens: impl IntoIterator<Item = LexResult<SpanData<Token>>> + 'a,
+    ) -> AstParser<'a> {
+        // Doc comments are not yet attached to declarations
+        let source = tokens.into_iter().filter(|token| {
+            !matches!(
+                token,
+                Ok(SpanData {
+                    value: Token::DocComment(_),
+                    ..
+                })
+            )
+        });
+        AstParser {
+            source: Box::new(source),
+            tokens: VecDeque::new(),
+            base: 0,
+            index: 0,
+            checkpoints: 0,
+            lex_error: None,
+            last_span: None,
+            loop_depth: 0,
+        }
     }
 
     fn try_run<T>(&mut self, parse: impl Fn(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
         let start = self.index;
-        match parse(self) {
-            ok @ Ok(_) => ok,
-            err @ Err(_) => {
-                self.index = start;
-                err
+        self.checkpoints += 1;
+        let res = parse(self);
+        self.checkpoints -= 1;
+        if res.is_err() {
+            self.index = start;
+        }
+        res
+    }
+
+    /// Drops the tokens which have been consumed, provided that nothing can
+    /// backtrack to them.
+    fn release_consumed(&mut self) {
+        if self.checkpoints == 0 {
+            let consumed = (self.index - self.base).min(self.tokens.len());
+            self.tokens.drain(..consumed);
+            self.base += consumed;
+        }
+    }
+
+    /// Runs `parse` with the given loop nesting depth, restoring the previous
+    /// depth afterwards.
+    fn with_loop_depth<T>(
+        &mut self,
+        loop_depth: usize,
+        parse: impl Fn(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<T> {
+        let prev_depth = self.loop_depth;
+        self.loop_depth = loop_depth;
+        let res = parse(self);
+        self.loop_depth = prev_depth;
+        res
+    }
+
+    fn get_token(&mut self) -> Option<&SpanData<Token>> {
+        self.peek_token(0)
+    }
+
+    /// Looks ahead `offset` tokens past the current token without consuming
+    /// anything, pulling more tokens from the source as required.
+    fn peek_token(&mut self, offset: usize) -> Option<&SpanData<Token>> {
+        let position = self.index + offset - self.base;
+        while self.tokens.len() <= position {
+            match self.source.next()? {
+                Ok(token) => {
+                    self.last_span = Some(token.span.clone());
+                    self.tokens.push_back(token);
+                }
+                Err(why) => {
+                    self.lex_error.get_or_insert(why);
+                    return None;
+                }
             }
         }
+        self.tokens.get(position)
+    }
+
+    fn is_next(&mut self, pred: impl Fn(&Token) -> bool) -> bool {
+        self.is_next_at(0, pred)
     }
 
-    fn get_token(&self) -> Option<&SpanData<Token>> {
-        self.tokens.get(self.index)
+    /// Whether the token `offset` tokens past the current one satisfies the
+    /// predicate.
+    fn is_next_at(&mut self, offset: usize, pred: impl Fn(&Token) -> bool) -> bool {
+        self.peek_token(offset)
+            .map(|token| pred(&token.value))
+            .unwrap_or(false)
+    }
+
+    /// Consumes the next token only if it satisfies the predicate.
+    fn try_consume(&mut self, pred: impl Fn(&Token) -> bool) -> Option<SpanData<Token>> {
+        if self.is_next(pred) {
+            self.next_token()
+        } else {
+            None
+        }
     }
 
     fn next_token(&mut self) -> Option<SpanData<Token>> {
@@ -31,42 +144,968 @@ impl AstParser {
         token
     }
 
-    fn parse_token(&mut self) -> ParseResult<SpanData<Token>> {
-        self.next_token().ok_or_else(|| ParseError::ExpectedToken)
+    /// A zero width span at the end of the final token, used to report errors
+    /// caused by running out of tokens.
+    fn eof_span(&self) -> Span {
+        match &self.last_span {
+            Some(span) => Span {
+                name: span.name.clone(),
+                file: span.file,
+                start: span.stop.clone(),
+                stop: span.stop.clone(),
+            },
+            None => Span {
+                name: "".into(),
+                file: None,
+                start: Point::default(),
+                stop: Point::default(),
+            },
+        }
+    }
+
+    /// Consumes the next token of any kind. `expected` describes what the
+    /// caller was looking for, should the tokens run out.
+    fn parse_token(&mut self, expected: &'static str) -> ParseResult<SpanData<Token>> {
+        self.next_token()
+            .ok_or_else(|| ParseError::eof(self.eof_span(), &[expected]))
     }
 
     fn try_parse_token(
         &mut self,
         pred: impl Fn(&Token) -> bool,
-        why: &'static str,
+        expected: &'static str,
     ) -> ParseResult<SpanData<Token>> {
-        let token = self.parse_token()?;
+        self.try_parse_token_of(pred, &[expected])
+    }
+
+    /// Consumes the next token if it satisfies the predicate, reporting the
+    /// descriptions in `expected` otherwise.
+    fn try_parse_token_of(
+        &mut self,
+        pred: impl Fn(&Token) -> bool,
+        expected: &[&'static str],
+    ) -> ParseResult<SpanData<Token>> {
+        let token = self
+            .next_token()
+            .ok_or_else(|| ParseError::eof(self.eof_span(), expected))?;
         if pred(&token.value) {
             Ok(token)
         } else {
-            Err(ParseError::custom(why))
+            Err(ParseError::expected(token, expected))
+        }
+    }
+
+    /// Parses every statement in the token stream. An error from the source
+    /// of the tokens takes priority over any parse error it may have caused.
+    pub fn parse_program(&mut self) -> ParseResult<Vec<SpanData<Statement>>> {
+        let mut statements = Vec::new();
+        while self.get_token().is_some() {
+            match self.try_parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(why) => return Err(self.lex_error.take().map_or(why, ParseError::Lex)),
+            }
+            self.release_consumed();
+        }
+        match self.lex_error.take() {
+            Some(why) => Err(ParseError::Lex(why)),
+            None => Ok(statements),
+        }
+    }
+
+    fn try_parse_identifier(&mut self) -> ParseResult<SpanData<Str>> {
+        self.try_run(|parser| {
+            let token = parser.parse_token("identifier")?;
+            match token.value {
+                Token::Identifier(name) => Ok(SpanData {
+                    span: token.span,
+                    value: name,
+                }),
+                _ => Err(ParseError::expected(token, &["identifier"])),
+            }
+        })
+    }
+
+    /// Parses a type expression. Union types bind the most loosely, followed
+    /// by optional types.
+    fn try_parse_type_expression(&mut self) -> ParseResult<SpanData<TypeExpression>> {
+        self.try_run(|parser| {
+            let first = parser.try_parse_optional_type()?;
+            if !parser.is_next(|token| matches!(token, Token::Pipe)) {
+                return Ok(first);
+            }
+
+            let mut span = first.span.clone();
+            let mut variants = vec![first];
+            while parser
+                .try_consume(|token| matches!(token, Token::Pipe))
+                .is_some()
+            {
+                let variant = parser.try_parse_optional_type()?;
+                span = span.to(&variant.span);
+                variants.push(variant);
+            }
+            Ok(SpanData {
+                span,
+                value: TypeExpression::Union(variants),
+            })
+        })
+    }
+
+    fn try_parse_optional_type(&mut self) -> ParseResult<SpanData<TypeExpression>> {
+        let mut type_expr = self.try_parse_primary_type()?;
+        while let Some(question) = self.try_consume(|token| matches!(token, Token::Question)) {
+            type_expr = SpanData {
+                span: type_expr.span.to(&question.span),
+                value: TypeExpression::Optional(Box::new(type_expr)),
+            };
         }
+        Ok(type_expr)
+    }
+
+    fn try_parse_primary_type(&mut self) -> ParseResult<SpanData<TypeExpression>> {
+        match self.get_token().map(|token| &token.value) {
+            Some(Token::Function) => self.try_parse_function_type(),
+            Some(Token::OpenParen) => self.try_parse_tuple_type(),
+            _ => self.try_parse_named_type(),
+        }
+    }
+
+    /// Parses a named type with optional type arguments, such as `Number` or
+    /// `Map<String, Number>`. At least one argument must be given between the
+    /// angle brackets.
+    fn try_parse_named_type(&mut self) -> ParseResult<SpanData<TypeExpression>> {
+        self.try_run(|parser| {
+            let name = parser.try_parse_identifier()?;
+            if parser
+                .try_consume(|token| matches!(token, Token::LessThan))
+                .is_none()
+            {
+                return Ok(SpanData {
+                    span: name.span,
+                    value: TypeExpression::Identifier(name.value),
+                });
+            }
+
+            let (args, stop) = parser.try_parse_list(
+                |token| matches!(token, Token::GreaterThan),
+                "`>`",
+                |parser| parser.try_parse_type_expression(),
+            )?;
+            if args.is_empty() {
+                return Err(ParseError::expected(stop, &["type"]));
+            }
+            Ok(SpanData {
+                span: name.span.to(&stop.span),
+                value: TypeExpression::Generic(GenericType { name, args }),
+            })
+        })
+    }
+
+    /// Parses a tuple type such as `(Number, String)`. A single type in
+    /// parentheses without a trailing comma is only grouped, not a tuple.
+    fn try_parse_tuple_type(&mut self) -> ParseResult<SpanData<TypeExpression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::OpenParen), "`(`")?;
+            if let Some(stop) = parser.try_consume(|token| matches!(token, Token::CloseParen)) {
+                return Ok(SpanData {
+                    span: start.span.to(&stop.span),
+                    value: TypeExpression::Tuple(Vec::new()),
+                });
+            }
+
+            // A single type without a trailing comma is only grouped
+            let first = parser.try_parse_type_expression()?;
+            if let Some(stop) = parser.try_consume(|token| matches!(token, Token::CloseParen)) {
+                return Ok(SpanData {
+                    span: start.span.to(&stop.span),
+                    value: first.value,
+                });
+            }
+
+            parser.try_parse_token_of(|token| matches!(token, Token::Comma), &["`,`", "`)`"])?;
+            let (rest, stop) = parser.try_parse_list(
+                |token| matches!(token, Token::CloseParen),
+                "`)`",
+                |parser| parser.try_parse_type_expression(),
+            )?;
+            let mut items = vec![first];
+            items.extend(rest);
+            Ok(SpanData {
+                span: start.span.to(&stop.span),
+                value: TypeExpression::Tuple(items),
+            })
+        })
+    }
+
+    /// Parses a function type such as `fn(Number, String) -> Bool`.
+    fn try_parse_function_type(&mut self) -> ParseResult<SpanData<TypeExpression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::Function), "`fn`")?;
+            parser.try_parse_token(|token| matches!(token, Token::OpenParen), "`(`")?;
+            let (args, stop) = parser.try_parse_list(
+                |token| matches!(token, Token::CloseParen),
+                "`)`",
+                |parser| parser.try_parse_type_expression(),
+            )?;
+            let return_type = parser.try_parse_return_type()?;
+            let span = match &return_type {
+                Some(return_type) => start.span.to(&return_type.span),
+                None => start.span.to(&stop.span),
+            };
+            Ok(SpanData {
+                span,
+                value: TypeExpression::Function(FunctionType {
+                    args,
+                    return_type: return_type.map(Box::new),
+                }),
+            })
+        })
+    }
+
+    /// Parses an identifier with an optional `: Type` annotation.
+    fn try_parse_typed_identifier(&mut self) -> ParseResult<Typed<Str>> {
+        self.try_run(|parser| {
+            let name = parser.try_parse_identifier()?;
+            let type_expr = match parser.try_consume(|token| matches!(token, Token::Colon)) {
+                Some(_) => Some(parser.try_parse_type_expression()?),
+                None => None,
+            };
+            Ok(Typed {
+                type_expr,
+                value: name.value,
+            })
+        })
+    }
+
+    /// Whether the upcoming tokens begin a named function, as opposed to an
+    /// anonymous `fn(..)` expression.
+    fn is_function_declaration_start(&mut self) -> bool {
+        self.is_next(|token| matches!(token, Token::Function))
+            && self.is_next_at(1, |token| matches!(token, Token::Identifier(_)))
+    }
+
+    fn is_declaration_start(&mut self) -> bool {
+        self.is_next(|token| matches!(token, Token::Public | Token::Let))
+            || self.is_function_declaration_start()
     }
 
     fn try_parse_statement(&mut self) -> ParseResult<SpanData<Statement>> {
-        todo!()
+        self.try_run(|parser| {
+            let public = parser.try_consume(|token| matches!(token, Token::Public));
+            let visibility = match public {
+                Some(_) => Visibility::Public,
+                None => Visibility::Private,
+            };
+
+            let statement = if parser.is_next(|token| matches!(token, Token::Let)) {
+                parser.try_parse_declaration(visibility)?
+            } else if public.is_some() && !parser.is_next(|token| matches!(token, Token::Function))
+            {
+                // Only declarations may be public
+                let expected = ["`let`", "`fn`"];
+                return Err(match parser.next_token() {
+                    Some(token) => ParseError::expected(token, &expected),
+                    None => ParseError::eof(parser.eof_span(), &expected),
+                });
+            } else if public.is_some() || parser.is_function_declaration_start() {
+                parser.try_parse_function(visibility)?
+            } else {
+                parser.try_parse_expression_statement()?
+            };
+
+            Ok(match public {
+                Some(public) => SpanData {
+                    span: public.span.to(&statement.span),
+                    value: statement.value,
+                },
+                None => statement,
+            })
+        })
+    }
+
+    fn try_parse_declaration(
+        &mut self,
+        visibility: Visibility,
+    ) -> ParseResult<SpanData<Statement>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::Let), "`let`")?;
+            let name = parser.try_parse_typed_identifier()?;
+            parser.try_parse_token(|token| matches!(token, Token::Equals), "`=`")?;
+            let value = parser.try_parse_expression()?;
+            let stop = parser.try_parse_token(|token| matches!(token, Token::Semicolon), "`;`")?;
+            Ok(SpanData {
+                span: start.span.to(&stop.span),
+                value: Statement::Declaration(Box::new(Declaration {
+                    visibility,
+                    name,
+                    value,
+                })),
+            })
+        })
+    }
+
+    fn try_parse_function(&mut self, visibility: Visibility) -> ParseResult<SpanData<Statement>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::Function), "`fn`")?;
+            let name = parser.try_parse_identifier()?;
+            let args = parser.try_parse_parameters()?;
+            let return_type = parser.try_parse_return_type()?;
+            let body = parser.with_loop_depth(0, |parser| parser.try_parse_block())?;
+            Ok(SpanData {
+                span: start.span.to(&body.span),
+                value: Statement::Function(Function {
+                    visibility,
+                    name: name.value,
+                    args,
+                    return_type,
+                    body: body.value,
+                }),
+            })
+        })
+    }
+
+    /// Parses an optional `-> Type` return type annotation.
+    fn try_parse_return_type(&mut self) -> ParseResult<Option<SpanData<TypeExpression>>> {
+        match self.try_consume(|token| matches!(token, Token::SingleArrow)) {
+            Some(_) => Ok(Some(self.try_parse_type_expression()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses comma separated items up to and including the closing token
+    /// matched by `is_close`. A trailing comma is permitted. The opening token
+    /// must already have been consumed.
+    fn try_parse_list<T>(
+        &mut self,
+        is_close: impl Fn(&Token) -> bool + Copy,
+        close: &'static str,
+        parse_item: impl Fn(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<(Vec<T>, SpanData<Token>)> {
+        self.try_run(|parser| {
+            let mut items = Vec::new();
+            loop {
+                if let Some(stop) = parser.try_consume(is_close) {
+                    return Ok((items, stop));
+                }
+                items.push(parse_item(parser)?);
+                if parser
+                    .try_consume(|token| matches!(token, Token::Comma))
+                    .is_none()
+                {
+                    let stop = parser.try_parse_token_of(is_close, &["`,`", close])?;
+                    return Ok((items, stop));
+                }
+            }
+        })
+    }
+
+    /// Parses a parenthesized, comma separated parameter list.
+    fn try_parse_parameters(&mut self) -> ParseResult<Vec<Typed<Str>>> {
+        self.try_run(|parser| {
+            parser.try_parse_token(|token| matches!(token, Token::OpenParen), "`(`")?;
+            let (params, _) = parser.try_parse_list(
+                |token| matches!(token, Token::CloseParen),
+                "`)`",
+                |parser| parser.try_parse_typed_identifier(),
+            )?;
+            Ok(params)
+        })
+    }
+
+    /// Whether the upcoming tokens begin a block-like expression, which ends
+    /// its statement at the closing brace without taking any operators that
+    /// follow, as in Rust. A map literal is not block-like, although `{}` is
+    /// an empty block here.
+    fn is_block_like_start(&mut self) -> bool {
+        self.is_next(|token| matches!(token, Token::If | Token::Loop | Token::While | Token::For))
+            || (self.is_next(|token| matches!(token, Token::OpenBrace))
+                && (self.is_empty_braces() || !self.is_map_start()))
+    }
+
+    /// Parses the expression beginning a statement. A block-like expression
+    /// is parsed alone, so that `if a { b }\n(c);` is two statements rather
+    /// than a call. Returns whether the expression was block-like.
+    fn try_parse_statement_expression(&mut self) -> ParseResult<(SpanData<Expression>, bool)> {
+        if !self.is_block_like_start() {
+            return Ok((self.try_parse_expression()?, false));
+        }
+        let expr = if self.is_next(|token| matches!(token, Token::OpenBrace)) {
+            self.try_parse_block_expression()?
+        } else {
+            self.try_parse_primary()?
+        };
+        Ok((expr, true))
+    }
+
+    fn try_parse_expression_statement(&mut self) -> ParseResult<SpanData<Statement>> {
+        self.try_run(|parser| {
+            let (expr, block_like) = parser.try_parse_statement_expression()?;
+            let semicolon = if block_like {
+                parser.try_consume(|token| matches!(token, Token::Semicolon))
+            } else {
+                Some(parser.try_parse_token(|token| matches!(token, Token::Semicolon), "`;`")?)
+            };
+            let span = match semicolon {
+                Some(semicolon) => expr.span.to(&semicolon.span),
+                None => expr.span.clone(),
+            };
+            Ok(SpanData {
+                span,
+                value: Statement::Expression(expr),
+            })
+        })
+    }
+
+    /// Parses a brace delimited block. A final expression without a trailing
+    /// semicolon becomes the value of the block.
+    fn try_parse_block(&mut self) -> ParseResult<SpanData<Block>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::OpenBrace), "`{`")?;
+            let mut body = Vec::new();
+            loop {
+                if let Some(stop) = parser.try_consume(|token| matches!(token, Token::CloseBrace)) {
+                    return Ok(SpanData {
+                        span: start.span.to(&stop.span),
+                        value: Block { body, value: None },
+                    });
+                }
+
+                if parser.is_declaration_start() {
+                    body.push(parser.try_parse_statement()?);
+                    continue;
+                }
+
+                let (expr, block_like) = parser.try_parse_statement_expression()?;
+                if let Some(semicolon) =
+                    parser.try_consume(|token| matches!(token, Token::Semicolon))
+                {
+                    body.push(SpanData {
+                        span: expr.span.to(&semicolon.span),
+                        value: Statement::Expression(expr),
+                    });
+                    continue;
+                }
+
+                if block_like && !parser.is_next(|token| matches!(token, Token::CloseBrace)) {
+                    body.push(SpanData {
+                        span: expr.span.clone(),
+                        value: Statement::Expression(expr),
+                    });
+                    continue;
+                }
+
+                let stop = parser.try_parse_token_of(
+                    |token| matches!(token, Token::CloseBrace),
+                    &["`;`", "`}`"],
+                )?;
+                return Ok(SpanData {
+                    span: start.span.to(&stop.span),
+                    value: Block {
+                        body,
+                        value: Some(Box::new(expr)),
+                    },
+                });
+            }
+        })
     }
 
     fn try_parse_expression(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_parse_assignment()
+    }
+
+    /// Parses an optional, right associative assignment. Assignment binds
+    /// more loosely than any other operator.
+    fn try_parse_assignment(&mut self) -> ParseResult<SpanData<Expression>> {
+        let target = self.try_parse_range()?;
+        let operator = match self.get_token().map(|token| &token.value) {
+            Some(Token::Equals) => None,
+            Some(Token::PlusEquals) => Some(BinaryOperator::Plus),
+            Some(Token::MinusEquals) => Some(BinaryOperator::Minus),
+            Some(Token::TimesEquals) => Some(BinaryOperator::Times),
+            Some(Token::DivideEquals) => Some(BinaryOperator::Divide),
+            Some(Token::ModuloEquals) => Some(BinaryOperator::Modulo),
+            _ => return Ok(target),
+        };
+
+        if !target.value.is_place() {
+            return Err(ParseError::InvalidAssignment(target.span));
+        }
+        self.next_token();
+
+        let value = self.try_parse_assignment()?;
+        Ok(SpanData {
+            span: target.span.to(&value.span),
+            value: Expression::Assign(Assign {
+                operator,
+                target: Box::new(target),
+                value: Box::new(value),
+            }),
+        })
+    }
+
+    /// Parses an optional, non-associative range. Ranges bind more loosely
+    /// than every binary operator.
+    fn try_parse_range(&mut self) -> ParseResult<SpanData<Expression>> {
+        let start = self.try_parse_binary(0)?;
+        let inclusive = match self.get_token().map(|token| &token.value) {
+            Some(Token::ExclusiveRange) => false,
+            Some(Token::InclusiveRange) => true,
+            _ => return Ok(start),
+        };
+        self.next_token();
+
+        let stop = self.try_parse_binary(0)?;
+        Ok(SpanData {
+            span: start.span.to(&stop.span),
+            value: Expression::Range(Range {
+                start: Box::new(start),
+                stop: Box::new(stop),
+                inclusive,
+            }),
+        })
+    }
+
+    /// Parses a chain of binary operators whose precedence is at least
+    /// `min_precedence`, using precedence climbing. All binary operators are
+    /// left associative.
+    fn try_parse_binary(&mut self, min_precedence: u8) -> ParseResult<SpanData<Expression>> {
+        let mut lhs = self.try_parse_unary()?;
+
+        while let Some(operator) = self
+            .get_token()
+            .and_then(|token| BinaryOperator::from_token(&token.value))
+        {
+            let precedence = operator.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            self.next_token();
+
+            let rhs = self.try_parse_binary(precedence + 1)?;
+            lhs = SpanData {
+                span: lhs.span.to(&rhs.span),
+                value: Expression::Binary(Binary {
+                    operator,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                }),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn try_parse_unary(&mut self) -> ParseResult<SpanData<Expression>> {
+        let operator = self
+            .get_token()
+            .and_then(|token| UnaryOperator::from_token(&token.value));
+        match operator {
+            Some(operator) => self.try_run(|parser| {
+                let start = parser.parse_token("unary operator")?;
+                let target = parser.try_parse_unary()?;
+                Ok(SpanData {
+                    span: start.span.to(&target.span),
+                    value: Expression::Unary(Unary {
+                        operator,
+                        target: Box::new(target),
+                    }),
+                })
+            }),
+            None => self.try_parse_postfix(),
+        }
+    }
+
+    /// Parses a primary expression followed by any number of calls, index
+    /// operations and member accesses, associating from left to right.
+    fn try_parse_postfix(&mut self) -> ParseResult<SpanData<Expression>> {
+        let mut expr = self.try_parse_primary()?;
+        loop {
+            let token = match self.try_consume(|token| {
+                matches!(token, Token::OpenParen | Token::OpenBracket | Token::Period)
+            }) {
+                Some(token) => token,
+                None => return Ok(expr),
+            };
+
+            expr = match token.value {
+                Token::OpenParen => {
+                    let (args, stop) = self.try_parse_list(
+                        |token| matches!(token, Token::CloseParen),
+                        "`)`",
+                        |parser| parser.try_parse_expression(),
+                    )?;
+                    SpanData {
+                        span: expr.span.to(&stop.span),
+                        value: Expression::Call(Call {
+                            target: Box::new(expr),
+                            args,
+                        }),
+                    }
+                }
+                Token::OpenBracket => {
+                    let index = self.try_parse_expression()?;
+                    let stop =
+                        self.try_parse_token(|token| matches!(token, Token::CloseBracket), "`]`")?;
+                    SpanData {
+                        span: expr.span.to(&stop.span),
+                        value: Expression::Index(Index {
+                            target: Box::new(expr),
+                            index: Box::new(index),
+                        }),
+                    }
+                }
+                _ => {
+                    let member = self.try_parse_identifier()?;
+                    SpanData {
+                        span: expr.span.to(&member.span),
+                        value: Expression::Member(Member {
+                            target: Box::new(expr),
+                            member,
+                        }),
+                    }
+                }
+            };
+        }
+    }
+
+    fn try_parse_primary(&mut self) -> ParseResult<SpanData<Expression>> {
+        match self.get_token().map(|token| &token.value) {
+            Some(Token::If) => return self.try_parse_if(),
+            Some(Token::Loop) => return self.try_parse_loop(),
+            Some(Token::While) => return self.try_parse_while(),
+            Some(Token::For) => return self.try_parse_for(),
+            Some(Token::Break | Token::Continue | Token::Return) => return self.try_parse_jump(),
+            _ => (),
+        }
+        if self.is_next(|token| matches!(token, Token::OpenBracket)) {
+            return self.try_parse_list_literal();
+        }
+        if self.is_map_start() {
+            return self.try_parse_map_literal();
+        }
+        if self.is_next(|token| matches!(token, Token::OpenBrace)) {
+            return self.try_parse_block_expression();
+        }
+        if self.is_next(|token| matches!(token, Token::Function)) {
+            return self.try_parse_lambda();
+        }
+        if self.is_next(|token| matches!(token, Token::OpenParen)) {
+            return match self.try_parse_parenthesized_lambda()? {
+                Some(lambda) => Ok(lambda),
+                None => self.try_parse_parentheses(),
+            };
+        }
         self.try_parse_token_expression()
     }
 
-    fn try_parse_parentheses(&mut self) -> ParseResult<SpanData<Expression>> {
+    fn try_parse_block_expression(&mut self) -> ParseResult<SpanData<Expression>> {
+        let block = self.try_parse_block()?;
+        Ok(SpanData {
+            span: block.span,
+            value: Expression::Block(block.value),
+        })
+    }
+
+    /// Parses the body of a lambda following its `=>`. A block body is used
+    /// as is, while any other expression becomes the value of a new block.
+    /// `{}` is an empty body rather than a map.
+    fn try_parse_lambda_body(&mut self) -> ParseResult<SpanData<Block>> {
+        let body = self.with_loop_depth(0, |parser| {
+            if parser.is_empty_braces() {
+                parser.try_parse_block_expression()
+            } else {
+                parser.try_parse_expression()
+            }
+        })?;
+        let block = match body.value {
+            Expression::Block(block) => block,
+            value => Block {
+                body: Vec::new(),
+                value: Some(Box::new(SpanData {
+                    span: body.span.clone(),
+                    value,
+                })),
+            },
+        };
+        Ok(SpanData {
+            span: body.span,
+            value: block,
+        })
+    }
+
+    /// Parses an anonymous function of the form `fn(args) -> T => expr` or
+    /// `fn(args) -> T { .. }`, where the return type is optional.
+    fn try_parse_lambda(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::Function), "`fn`")?;
+            let args = parser.try_parse_parameters()?;
+            let return_type = parser.try_parse_return_type()?;
+            let body = if parser
+                .try_consume(|token| matches!(token, Token::DoubleArrow))
+                .is_some()
+            {
+                parser.try_parse_lambda_body()?
+            } else {
+                parser.with_loop_depth(0, |parser| parser.try_parse_block())?
+            };
+            Ok(SpanData {
+                span: start.span.to(&body.span),
+                value: Expression::Lambda(Lambda {
+                    args,
+                    return_type,
+                    body: body.value,
+                }),
+            })
+        })
+    }
+
+    /// Parses an anonymous function of the form `(args) -> T => expr`, where
+    /// the return type is optional. Nothing is consumed and `None` is
+    /// returned if the tokens do not begin a lambda, such as for a
+    /// parenthesized expression. Once the `=>` is found, errors in the body
+    /// are reported as is.
+    fn try_parse_parenthesized_lambda(&mut self) -> ParseResult<Option<SpanData<Expression>>> {
+        let header = self.try_run(|parser| {
+            let start = parser
+                .get_token()
+                .cloned()
+                .ok_or_else(|| ParseError::eof(parser.eof_span(), &["`(`"]))?;
+            let args = parser.try_parse_parameters()?;
+            let return_type = parser.try_parse_return_type()?;
+            parser.try_parse_token(|token| matches!(token, Token::DoubleArrow), "`=>`")?;
+            Ok((start, args, return_type))
+        });
+        let (start, args, return_type) = match header {
+            Ok(header) => header,
+            Err(_) => return Ok(None),
+        };
+
+        let body = self.try_parse_lambda_body()?;
+        Ok(Some(SpanData {
+            span: start.span.to(&body.span),
+            value: Expression::Lambda(Lambda {
+                args,
+                return_type,
+                body: body.value,
+            }),
+        }))
+    }
+
+    fn try_parse_list_literal(&mut self) -> ParseResult<SpanData<Expression>> {
         self.try_run(|parser| {
-            let start = parser.try_parse_token(
-                |token| matches!(token, Token::OpenParen),
-                "expected open paren",
+            let start =
+                parser.try_parse_token(|token| matches!(token, Token::OpenBracket), "`[`")?;
+            let (items, stop) = parser.try_parse_list(
+                |token| matches!(token, Token::CloseBracket),
+                "`]`",
+                |parser| parser.try_parse_expression(),
             )?;
-            let inner = parser.try_parse_expression()?;
-            let stop = parser.try_parse_token(
-                |token| matches!(token, Token::CloseParen),
-                "expected close paren",
+            Ok(SpanData {
+                span: start.span.to(&stop.span),
+                value: Expression::List(items),
+            })
+        })
+    }
+
+    /// Whether the upcoming tokens are `{}`, which is an empty map within an
+    /// expression but an empty block at the start of a statement or as the
+    /// body of a lambda.
+    fn is_empty_braces(&mut self) -> bool {
+        self.is_next(|token| matches!(token, Token::OpenBrace))
+            && self.is_next_at(1, |token| matches!(token, Token::CloseBrace))
+    }
+
+    /// Whether the upcoming tokens begin a map literal rather than a block.
+    /// A map is either `{}` or a brace followed by a string or identifier key
+    /// and a colon, which can never begin a statement.
+    fn is_map_start(&mut self) -> bool {
+        self.is_empty_braces()
+            || (self.is_next(|token| matches!(token, Token::OpenBrace))
+                && self.is_next_at(1, |token| {
+                    matches!(token, Token::String(_) | Token::Identifier(_))
+                })
+                && self.is_next_at(2, |token| matches!(token, Token::Colon)))
+    }
+
+    fn try_parse_map_entry(&mut self) -> ParseResult<MapEntry> {
+        self.try_run(|parser| {
+            let token = parser.parse_token("map key")?;
+            let key = match token.value {
+                Token::String(key) | Token::Identifier(key) => SpanData {
+                    span: token.span,
+                    value: key,
+                },
+                _ => return Err(ParseError::expected(token, &["map key"])),
+            };
+            parser.try_parse_token(|token| matches!(token, Token::Colon), "`:`")?;
+            let value = parser.try_parse_expression()?;
+            Ok(MapEntry { key, value })
+        })
+    }
+
+    fn try_parse_map_literal(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::OpenBrace), "`{`")?;
+            let (entries, stop) = parser.try_parse_list(
+                |token| matches!(token, Token::CloseBrace),
+                "`}`",
+                |parser| parser.try_parse_map_entry(),
             )?;
+            Ok(SpanData {
+                span: start.span.to(&stop.span),
+                value: Expression::Map(entries),
+            })
+        })
+    }
+
+    fn try_parse_loop_body(&mut self) -> ParseResult<SpanData<Block>> {
+        self.with_loop_depth(self.loop_depth + 1, |parser| parser.try_parse_block())
+    }
+
+    fn try_parse_loop(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::Loop), "`loop`")?;
+            let body = parser.try_parse_loop_body()?;
+            Ok(SpanData {
+                span: start.span.to(&body.span),
+                value: Expression::Loop(Loop { body: body.value }),
+            })
+        })
+    }
+
+    fn try_parse_while(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::While), "`while`")?;
+            let condition = parser.try_parse_expression()?;
+            let body = parser.try_parse_loop_body()?;
+            Ok(SpanData {
+                span: start.span.to(&body.span),
+                value: Expression::While(While {
+                    condition: Box::new(condition),
+                    body: body.value,
+                }),
+            })
+        })
+    }
+
+    fn try_parse_for(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::For), "`for`")?;
+            let binding = parser.try_parse_identifier()?;
+            parser.try_parse_token(|token| matches!(token, Token::In), "`in`")?;
+            let iterable = parser.try_parse_expression()?;
+            let body = parser.try_parse_loop_body()?;
+            Ok(SpanData {
+                span: start.span.to(&body.span),
+                value: Expression::For(For {
+                    binding: binding.value,
+                    iterable: Box::new(iterable),
+                    body: body.value,
+                }),
+            })
+        })
+    }
+
+    /// Whether the next token cannot begin an expression, meaning that an
+    /// optional `break` or `return` value is absent.
+    fn is_expression_end(&mut self) -> bool {
+        self.get_token().is_none()
+            || self.is_next(|token| {
+                matches!(
+                    token,
+                    Token::Semicolon
+                        | Token::CloseBrace
+                        | Token::CloseParen
+                        | Token::CloseBracket
+                        | Token::Comma
+                )
+            })
+    }
+
+    /// Parses `break`, `continue` or `return`, along with an optional value.
+    fn try_parse_jump(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.parse_token("`break`, `continue` or `return`")?;
+            if parser.loop_depth == 0 {
+                let message = match start.value {
+                    Token::Break => Some("`break` outside of loop"),
+                    Token::Continue => Some("`continue` outside of loop"),
+                    _ => None,
+                };
+                if let Some(message) = message {
+                    return Err(ParseError::custom(start.span, message));
+                }
+            }
+
+            let mut span = start.span.clone();
+            let value = if matches!(start.value, Token::Continue) || parser.is_expression_end() {
+                None
+            } else {
+                let value = parser.try_parse_expression()?;
+                span = span.to(&value.span);
+                Some(Box::new(value))
+            };
+
+            let expr = match start.value {
+                Token::Break => Expression::Break(value),
+                Token::Continue => Expression::Continue,
+                Token::Return => Expression::Return(value),
+                _ => {
+                    return Err(ParseError::expected(
+                        start,
+                        &["`break`", "`continue`", "`return`"],
+                    ))
+                }
+            };
+            Ok(SpanData { span, value: expr })
+        })
+    }
+
+    fn try_parse_if(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::If), "`if`")?;
+            let condition = parser.try_parse_expression()?;
+            let then = parser.try_parse_block()?;
+            let mut span = start.span.to(&then.span);
+
+            let otherwise = if parser
+                .try_consume(|token| matches!(token, Token::Else))
+                .is_some()
+            {
+                let otherwise = if parser.is_next(|token| matches!(token, Token::If)) {
+                    let nested = parser.try_parse_if()?;
+                    SpanData {
+                        span: nested.span.clone(),
+                        value: Block {
+                            body: Vec::new(),
+                            value: Some(Box::new(nested)),
+                        },
+                    }
+                } else {
+                    parser.try_parse_block()?
+                };
+                span = span.to(&otherwise.span);
+                Some(otherwise.value)
+            } else {
+                None
+            };
+
+            Ok(SpanData {
+                span,
+                value: Expression::If(If {
+                    condition: Box::new(condition),
+                    then: then.value,
+                    otherwise,
+                }),
+            })
+        })
+    }
+
+    fn try_parse_parentheses(&mut self) -> ParseResult<SpanData<Expression>> {
+        self.try_run(|parser| {
+            let start = parser.try_parse_token(|token| matches!(token, Token::OpenParen), "`(`")?;
+            let inner = parser.try_parse_expression()?;
+            let stop = parser.try_parse_token(|token| matches!(token, Token::CloseParen), "`)`")?;
             Ok(SpanData {
                 span: start.span.to(&stop.span),
                 value: inner.value,
@@ -74,17 +1113,50 @@ impl AstParser {
         })
     }
 
+    /// Parses the tokens embedded in an interpolated string, which must form
+    /// exactly one expression.
+    fn parse_interpolation(
+        tokens: SpanData<Vec<SpanData<Token>>>,
+    ) -> ParseResult<SpanData<Expression>> {
+        if tokens.value.is_empty() {
+            return Err(ParseError::custom(
+                tokens.span,
+                "empty string interpolation",
+            ));
+        }
+        let mut parser = AstParser::new(tokens.value);
+        let expr = parser.try_parse_expression()?;
+        match parser.next_token() {
+            Some(token) => Err(ParseError::expected(token, &["`}`"])),
+            None => Ok(expr),
+        }
+    }
+
     fn try_parse_token_expression(&mut self) -> ParseResult<SpanData<Expression>> {
         self.try_run(|parser| {
-            let token = parser.parse_token()?;
+            let token = parser.parse_token("expression")?;
             let expr = match token.value {
-                Token::Boolean(b) => Ok(Expression::Boolean(b)),
-                Token::Number(n) => Ok(Expression::Number(n)),
-                Token::String(s) => Ok(Expression::String(s)),
-                Token::Identifier(i) => Ok(Expression::Identifier(i)),
-                Token::None => Ok(Expression::None),
-                _ => Err(ParseError::ExpectedToken),
-            }?;
+                Token::Boolean(b) => Expression::Boolean(b),
+                Token::Integer(n) => Expression::Integer(n),
+                Token::Float(n) => Expression::Float(n),
+                Token::String(s) => Expression::String(s),
+                Token::InterpolatedString(segments) => {
+                    let segments = segments
+                        .into_iter()
+                        .map(|segment| match segment {
+                            StringSegment::Literal(text) => Ok(InterpolationSegment::Literal(text)),
+                            StringSegment::Interpolation(tokens) => {
+                                let expr = Self::parse_interpolation(tokens)?;
+                                Ok(InterpolationSegment::Expression(Box::new(expr)))
+                            }
+                        })
+                        .collect::<ParseResult<_>>()?;
+                    Expression::Interpolated(segments)
+                }
+                Token::Identifier(i) => Expression::Identifier(i),
+                Token::None => Expression::None,
+                _ => return Err(ParseError::expected(token, &["expression"])),
+            };
             Ok(SpanData {
                 span: token.span,
                 value: expr,
@@ -92,3 +1164,864 @@ impl AstParser {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Lexer;
+
+    fn operator(operator: BinaryOperator) -> &'static str {
+        match operator {
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::Times => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::Equals => "==",
+            BinaryOperator::NotEquals => "!=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::LessThanEquals => "<=",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::GreaterThanEquals => ">=",
+        }
+    }
+
+    fn list<T>(items: &[T], show: impl Fn(&T) -> String) -> String {
+        items.iter().map(show).collect::<Vec<_>>().join(" ")
+    }
+
+    fn show_type(type_expr: &TypeExpression) -> String {
+        match type_expr {
+            TypeExpression::Identifier(name) => name.to_string(),
+            TypeExpression::Generic(generic) => format!(
+                "{}<{}>",
+                generic.name.value,
+                list(&generic.args, |arg| show_type(&arg.value))
+            ),
+            TypeExpression::Optional(inner) => format!("(? {})", show_type(&inner.value)),
+            TypeExpression::Union(variants) => {
+                format!(
+                    "(| {})",
+                    list(variants, |variant| show_type(&variant.value))
+                )
+            }
+            TypeExpression::Tuple(items) => {
+                format!("(tuple {})", list(items, |item| show_type(&item.value)))
+            }
+            TypeExpression::Function(function) => {
+                let args = list(&function.args, |arg| show_type(&arg.value));
+                match &function.return_type {
+                    Some(return_type) => {
+                        format!("(fn ({}) {})", args, show_type(&return_type.value))
+                    }
+                    None => format!("(fn ({}))", args),
+                }
+            }
+        }
+    }
+
+    fn show_typed(typed: &Typed<Str>) -> String {
+        match &typed.type_expr {
+            Some(type_expr) => format!("{}: {}", typed.value, show_type(&type_expr.value)),
+            None => typed.value.to_string(),
+        }
+    }
+
+    fn show_function(
+        args: &[Typed<Str>],
+        return_type: &Option<SpanData<TypeExpression>>,
+        body: &Block,
+    ) -> String {
+        let args = args.iter().map(show_typed).collect::<Vec<_>>().join(", ");
+        match return_type {
+            Some(return_type) => format!(
+                "({}) -> {} {}",
+                args,
+                show_type(&return_type.value),
+                show_block(body)
+            ),
+            None => format!("({}) {}", args, show_block(body)),
+        }
+    }
+
+    fn show_block(block: &Block) -> String {
+        let mut items: Vec<_> = block
+            .body
+            .iter()
+            .map(|statement| format!("{};", show_statement(&statement.value)))
+            .collect();
+        if let Some(value) = &block.value {
+            items.push(show(&value.value));
+        }
+        format!("{{{}}}", items.join(" "))
+    }
+
+    fn show_jump(name: &str, value: &Option<Box<SpanData<Expression>>>) -> String {
+        match value {
+            Some(value) => format!("({} {})", name, show(&value.value)),
+            None => format!("({})", name),
+        }
+    }
+
+    /// Renders an expression as an S-expression, making its structure easy
+    /// to compare.
+    fn show(expr: &Expression) -> String {
+        match expr {
+            Expression::None => "None".into(),
+            Expression::Integer(n) => n.to_string(),
+            Expression::Float(n) => format!("{:?}", n),
+            Expression::Boolean(b) => if *b { "True" } else { "False" }.into(),
+            Expression::String(s) => format!("{:?}", s),
+            Expression::Interpolated(segments) => format!(
+                "(str {})",
+                list(segments, |segment| match segment {
+                    InterpolationSegment::Literal(text) => format!("{:?}", text),
+                    InterpolationSegment::Expression(expr) => show(&expr.value),
+                })
+            ),
+            Expression::Identifier(name) => name.to_string(),
+            Expression::List(items) => format!("[{}]", list(items, |item| show(&item.value))),
+            Expression::Map(entries) => format!(
+                "(map {})",
+                list(entries, |entry| format!(
+                    "({} {})",
+                    entry.key.value,
+                    show(&entry.value.value)
+                ))
+            ),
+            Expression::Lambda(lambda) => format!(
+                "(fn {})",
+                show_function(&lambda.args, &lambda.return_type, &lambda.body)
+            ),
+            Expression::Unary(unary) => {
+                let operator = match unary.operator {
+                    UnaryOperator::Negative => "-",
+                    UnaryOperator::Not => "!",
+                };
+                format!("({} {})", operator, show(&unary.target.value))
+            }
+            Expression::Binary(binary) => format!(
+                "({} {} {})",
+                operator(binary.operator),
+                show(&binary.lhs.value),
+                show(&binary.rhs.value)
+            ),
+            Expression::Call(call) => format!(
+                "(call {} [{}])",
+                show(&call.target.value),
+                list(&call.args, |arg| show(&arg.value))
+            ),
+            Expression::Index(index) => format!(
+                "(index {} {})",
+                show(&index.target.value),
+                show(&index.index.value)
+            ),
+            Expression::Member(member) => {
+                format!("(. {} {})", show(&member.target.value), member.member.value)
+            }
+            Expression::Assign(assign) => format!(
+                "({}= {} {})",
+                assign.operator.map(operator).unwrap_or(""),
+                show(&assign.target.value),
+                show(&assign.value.value)
+            ),
+            Expression::Range(range) => format!(
+                "({} {} {})",
+                if range.inclusive { "..=" } else { ".." },
+                show(&range.start.value),
+                show(&range.stop.value)
+            ),
+            Expression::Block(block) => show_block(block),
+            Expression::If(expr) => match &expr.otherwise {
+                Some(otherwise) => format!(
+                    "(if {} {} {})",
+                    show(&expr.condition.value),
+                    show_block(&expr.then),
+                    show_block(otherwise)
+                ),
+                None => format!(
+                    "(if {} {})",
+                    show(&expr.condition.value),
+                    show_block(&expr.then)
+                ),
+            },
+            Expression::Loop(expr) => format!("(loop {})", show_block(&expr.body)),
+            Expression::While(expr) => format!(
+                "(while {} {})",
+                show(&expr.condition.value),
+                show_block(&expr.body)
+            ),
+            Expression::For(expr) => format!(
+                "(for {} {} {})",
+                expr.binding,
+                show(&expr.iterable.value),
+                show_block(&expr.body)
+            ),
+            Expression::Break(value) => show_jump("break", value),
+            Expression::Continue => "(continue)".into(),
+            Expression::Return(value) => show_jump("return", value),
+        }
+    }
+
+    fn show_statement(statement: &Statement) -> String {
+        match statement {
+            Statement::Declaration(declaration) => format!(
+                "(let {} {})",
+                show_typed(&declaration.name),
+                show(&declaration.value.value)
+            ),
+            Statement::Function(function) => format!(
+                "(fn {} {})",
+                function.name,
+                show_function(&function.args, &function.return_type, &function.body)
+            ),
+            Statement::Expression(expr) => show(&expr.value),
+        }
+    }
+
+    fn parser(src: &str) -> AstParser<'_> {
+        AstParser::from_stream(Lexer::new("<test>", src))
+    }
+
+    /// Parses `src` as a single expression, which must use every token.
+    fn parse_expression(src: &str) -> SpanData<Expression> {
+        let mut parser = parser(src);
+        let expr = parser
+            .try_parse_expression()
+            .unwrap_or_else(|why| panic!("failed to parse {:?}: {}", src, why));
+        if let Some(token) = parser.next_token() {
+            panic!("unexpected {} after parsing {:?}", token.value, src);
+        }
+        expr
+    }
+
+    fn parse_program(src: &str) -> Vec<String> {
+        parser(src)
+            .parse_program()
+            .unwrap_or_else(|why| panic!("failed to parse {:?}: {}", src, why))
+            .iter()
+            .map(|statement| show_statement(&statement.value))
+            .collect()
+    }
+
+    fn parse_error(src: &str) -> ParseError {
+        match parser(src).parse_program() {
+            Ok(_) => panic!("parsing {:?} should fail", src),
+            Err(why) => why,
+        }
+    }
+
+    fn assert_expressions(cases: &[(&str, &str)]) {
+        for (src, expected) in cases {
+            let expr = parse_expression(src);
+            assert_eq!(show(&expr.value), *expected, "parsing {:?}", src);
+        }
+    }
+
+    /// The source text covered by a span.
+    fn text<'a>(src: &'a str, span: &Span) -> &'a str {
+        &src[span.range()]
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_expressions(&[
+            ("1 + 2 * 3", "(+ 1 (* 2 3))"),
+            ("1 * 2 + 3", "(+ (* 1 2) 3)"),
+            ("1 + 2 % 3 / 4", "(+ 1 (/ (% 2 3) 4))"),
+            ("a < b + 1", "(< a (+ b 1))"),
+            ("a == b < c", "(== a (< b c))"),
+            ("a != b >= c - d", "(!= a (>= b (- c d)))"),
+            ("a <= b == c > d", "(== (<= a b) (> c d))"),
+        ]);
+    }
+
+    #[test]
+    fn left_associativity() {
+        assert_expressions(&[
+            ("1 - 2 - 3", "(- (- 1 2) 3)"),
+            ("1 / 2 / 3", "(/ (/ 1 2) 3)"),
+            ("1 - 2 + 3", "(+ (- 1 2) 3)"),
+            ("a == b == c", "(== (== a b) c)"),
+            ("1 * 2 - 3 * 4 - 5", "(- (- (* 1 2) (* 3 4)) 5)"),
+        ]);
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_expressions(&[
+            ("-a.b", "(- (. a b))"),
+            ("-f(x)", "(- (call f [x]))"),
+            ("!a == b", "(== (! a) b)"),
+            ("-a * b", "(* (- a) b)"),
+            ("a - -b", "(- a (- b))"),
+            ("!!a", "(! (! a))"),
+            ("-(a + b)", "(- (+ a b))"),
+        ]);
+    }
+
+    #[test]
+    fn parenthesized_grouping() {
+        assert_expressions(&[
+            ("(1 + 2) * 3", "(* (+ 1 2) 3)"),
+            ("1 - (2 - 3)", "(- 1 (- 2 3))"),
+            ("((a))", "a"),
+            ("!(a == b)", "(! (== a b))"),
+            ("(a + b).c", "(. (+ a b) c)"),
+        ]);
+    }
+
+    #[test]
+    fn operator_spans() {
+        let src = "-a.b + (c) * d";
+        let expr = parse_expression(src);
+        assert_eq!(text(src, &expr.span), src);
+        let Expression::Binary(sum) = &expr.value else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(text(src, &sum.lhs.span), "-a.b");
+        assert_eq!(text(src, &sum.rhs.span), "(c) * d");
+
+        let Expression::Binary(product) = &sum.rhs.value else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(text(src, &product.lhs.span), "(c)");
+        assert_eq!(text(src, &product.rhs.span), "d");
+
+        let Expression::Unary(negative) = &sum.lhs.value else {
+            panic!("expected a unary expression");
+        };
+        assert_eq!(text(src, &negative.target.span), "a.b");
+    }
+
+    #[test]
+    fn block_values() {
+        assert_expressions(&[
+            ("{ 1 }", "{1}"),
+            ("{ 1; }", "{1;}"),
+            ("{ let a = 1; a + 1 }", "{(let a 1); (+ a 1)}"),
+            ("{ f(); { g() } }", "{(call f []); {(call g [])}}"),
+            ("{ if a { 1 } 2 }", "{(if a {1}); 2}"),
+            ("{ if a { 1 } else { 2 } }", "{(if a {1} {2})}"),
+            ("{ loop {} [1] }", "{(loop {}); [1]}"),
+        ]);
+    }
+
+    #[test]
+    fn if_expressions() {
+        assert_expressions(&[
+            ("if a { 1 }", "(if a {1})"),
+            ("if a { 1 } else { 2 }", "(if a {1} {2})"),
+            ("if a { 1 } else if b { 2 }", "(if a {1} {(if b {2})})"),
+            (
+                "if a { 1 } else if b { 2 } else { 3 }",
+                "(if a {1} {(if b {2} {3})})",
+            ),
+            (
+                "if a == b { f(); 1 } else { 2 }",
+                "(if (== a b) {(call f []); 1} {2})",
+            ),
+            ("if a { 1 } else { 2 } + 3", "(+ (if a {1} {2}) 3)"),
+        ]);
+        assert_eq!(
+            parse_program("let x = if a { 1 } else { 2 };"),
+            vec!["(let x (if a {1} {2}))"]
+        );
+    }
+
+    #[test]
+    fn block_like_statements() {
+        let cases: [(&str, &[&str]); 6] = [
+            ("if a { b }\n(c);", &["(if a {b})", "c"]),
+            ("fn f() { loop {} [1] }", &["(fn f () {(loop {}); [1]})"]),
+            ("if a {1} else {2}\n-1;", &["(if a {1} {2})", "(- 1)"]),
+            ("while a { b }\n[c].d;", &["(while a {b})", "(. [c] d)"]),
+            ("{ a }\n(b);", &["{a}", "b"]),
+            ("for x in y {};\nx;", &["(for x y {})", "x"]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(parse_program(src), expected, "parsing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn loops() {
+        assert_expressions(&[
+            ("loop { f() }", "(loop {(call f [])})"),
+            ("while a < b { a += 1; }", "(while (< a b) {(+= a 1);})"),
+            ("for x in xs { f(x) }", "(for x xs {(call f [x])})"),
+            ("for x in a..b {}", "(for x (.. a b) {})"),
+            ("for x in a..=b {}", "(for x (..= a b) {})"),
+            ("for x in 0..n + 1 {}", "(for x (.. 0 (+ n 1)) {})"),
+            ("a + 1..=b * 2", "(..= (+ a 1) (* b 2))"),
+        ]);
+    }
+
+    #[test]
+    fn jumps() {
+        assert_expressions(&[
+            ("loop { break }", "(loop {(break)})"),
+            ("loop { break; }", "(loop {(break);})"),
+            ("loop { break 1 + 2 }", "(loop {(break (+ 1 2))})"),
+            ("loop { continue; }", "(loop {(continue);})"),
+            ("loop { if a { break } }", "(loop {(if a {(break)})})"),
+            (
+                "while a { for b in c { continue } }",
+                "(while a {(for b c {(continue)})})",
+            ),
+            (
+                "loop { f(fn() => 1, break) }",
+                "(loop {(call f [(fn () {1}) (break)])})",
+            ),
+        ]);
+        assert_eq!(
+            parse_program("fn f() { return; }\nfn g() { return 1 }\nreturn [return];"),
+            vec![
+                "(fn f () {(return);})",
+                "(fn g () {(return 1)})",
+                "(return [(return)])",
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_outside_loops() {
+        let cases = [
+            ("break;", "break", "`break` outside of loop"),
+            ("continue;", "continue", "`continue` outside of loop"),
+            ("if a { break; }", "break", "`break` outside of loop"),
+            ("loop {} break;", "break", "`break` outside of loop"),
+            (
+                "loop { fn f() { break; } }",
+                "break",
+                "`break` outside of loop",
+            ),
+            (
+                "while a { fn() { continue; } }",
+                "continue",
+                "`continue` outside of loop",
+            ),
+            (
+                "for x in y { let f = () => break; }",
+                "break",
+                "`break` outside of loop",
+            ),
+        ];
+        for (src, jump, message) in cases {
+            let why = parse_error(src);
+            assert_eq!(why.to_string(), message, "parsing {:?}", src);
+            assert_eq!(text(src, why.span()), jump, "parsing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn postfix_chains() {
+        assert_expressions(&[
+            (
+                "obj.field.method(a, b,)[i](x)",
+                "(call (index (call (. (. obj field) method) [a b]) i) [x])",
+            ),
+            ("f()", "(call f [])"),
+            ("f(a,)", "(call f [a])"),
+            ("f(a)(b)", "(call (call f [a]) [b])"),
+            ("a[0][1]", "(index (index a 0) 1)"),
+            ("a.b.c", "(. (. a b) c)"),
+            ("f(a + 1, [b, c])", "(call f [(+ a 1) [b c]])"),
+            ("a[i + 1].b", "(. (index a (+ i 1)) b)"),
+            ("3.abs()", "(call (. 3 abs) [])"),
+        ]);
+
+        let src = "obj.field.method(a, b,)[i](x)";
+        let expr = parse_expression(src);
+        let Expression::Call(call) = &expr.value else {
+            panic!("expected a call");
+        };
+        assert_eq!(text(src, &call.target.span), "obj.field.method(a, b,)[i]");
+        assert_eq!(text(src, &call.args[0].span), "x");
+        let Expression::Index(index) = &call.target.value else {
+            panic!("expected an index");
+        };
+        assert_eq!(text(src, &index.target.span), "obj.field.method(a, b,)");
+    }
+
+    #[test]
+    fn invalid_argument_lists() {
+        for src in ["f(,)", "f(a,,)", "f(a b)", "a.1", "a[]", "f(a"] {
+            let res = parser(src).try_parse_expression();
+            assert!(res.is_err(), "parsing {:?} gave {:?}", src, res);
+        }
+    }
+
+    #[test]
+    fn collection_literals() {
+        assert_expressions(&[
+            ("[]", "[]"),
+            ("[1, 2, 3]", "[1 2 3]"),
+            ("[1, 2, 3,]", "[1 2 3]"),
+            ("[[1], [], [2, [3]]]", "[[1] [] [2 [3]]]"),
+            ("{}", "(map )"),
+            ("{ a: 1 }", "(map (a 1))"),
+            ("{ \"a\": 1, b: 2, }", "(map (a 1) (b 2))"),
+            (
+                "{ a: [1, { b: {} }], c: { d: [] } }",
+                "(map (a [1 (map (b (map )))]) (c (map (d []))))",
+            ),
+            ("[{ a: 1 }, {}]", "[(map (a 1)) (map )]"),
+            ("{ a: if b { c } else { d } }", "(map (a (if b {c} {d})))"),
+        ]);
+    }
+
+    #[test]
+    fn map_block_disambiguation() {
+        assert_expressions(&[
+            ("{ a }", "{a}"),
+            ("{ a; }", "{a;}"),
+            ("{ \"a\" }", "{\"a\"}"),
+            ("{ a: 1 }", "(map (a 1))"),
+            ("{ {} }", "{{}}"),
+            ("{ {}; 1 }", "{{}; 1}"),
+            ("{ { a: 1 } }", "{(map (a 1))}"),
+            ("fn(x) => {}", "(fn (x) {})"),
+            ("(x) => {}", "(fn (x) {})"),
+            ("(x) => { a: x }", "(fn (x) {(map (a x))})"),
+            ("(x) => { x }", "(fn (x) {x})"),
+        ]);
+        assert_eq!(
+            parse_program("{}\n{ }\nlet m = {};\n{ a: 1 };"),
+            vec!["{}", "{}", "(let m (map ))", "(map (a 1))"]
+        );
+    }
+
+    #[test]
+    fn lambdas() {
+        assert_expressions(&[
+            ("fn(x, y) => x + y", "(fn (x, y) {(+ x y)})"),
+            ("fn() { f(); 1 }", "(fn () {(call f []); 1})"),
+            (
+                "fn(x: Number) -> Number { x }",
+                "(fn (x: Number) -> Number {x})",
+            ),
+            ("fn(x,) -> Bool => !x", "(fn (x) -> Bool {(! x)})"),
+            (
+                "(x: Number) -> Number => x * 2",
+                "(fn (x: Number) -> Number {(* x 2)})",
+            ),
+            ("() => 1", "(fn () {1})"),
+            ("(a, b) => a", "(fn (a, b) {a})"),
+            ("(x) => (y) => x + y", "(fn (x) {(fn (y) {(+ x y)})})"),
+            ("f((x) => x, 1)", "(call f [(fn (x) {x}) 1])"),
+            ("(fn(x) => x)(1)", "(call (fn (x) {x}) [1])"),
+        ]);
+    }
+
+    #[test]
+    fn lambda_or_grouping() {
+        assert_expressions(&[
+            ("(x)", "x"),
+            ("(x) + 1", "(+ x 1)"),
+            ("(x)(y)", "(call x [y])"),
+            ("(x) => x", "(fn (x) {x})"),
+            ("(x: T) => x", "(fn (x: T) {x})"),
+            ("(x) -> T => x", "(fn (x) -> T {x})"),
+            ("(x == y)", "(== x y)"),
+        ]);
+
+        let src = "(x) -> T => x + 1";
+        let expr = parse_expression(src);
+        assert_eq!(text(src, &expr.span), src);
+        let Expression::Lambda(lambda) = &expr.value else {
+            panic!("expected a lambda");
+        };
+        assert_eq!(
+            text(src, &lambda.body.value.as_ref().unwrap().span),
+            "x + 1"
+        );
+    }
+
+    #[test]
+    fn return_type_annotations() {
+        assert_eq!(
+            parse_program(
+                "fn f(a: Number, b) -> Number { a }\n\
+                 pub fn g() -> fn(Number) -> Bool { (n) => n > 0 }\n\
+                 fn h() {}"
+            ),
+            vec![
+                "(fn f (a: Number, b) -> Number {a})",
+                "(fn g () -> (fn (Number) Bool) {(fn (n) {(> n 0)})})",
+                "(fn h () {})",
+            ]
+        );
+        assert!(matches!(
+            parse_error("fn f() -> { 1 }"),
+            ParseError::ExpectedToken { .. }
+        ));
+    }
+
+    #[test]
+    fn assignments() {
+        assert_expressions(&[
+            ("a = 1", "(= a 1)"),
+            ("a += 1", "(+= a 1)"),
+            ("a -= 1", "(-= a 1)"),
+            ("a *= 1", "(*= a 1)"),
+            ("a /= 1", "(/= a 1)"),
+            ("a %= 1", "(%= a 1)"),
+            ("a[i] += 1", "(+= (index a i) 1)"),
+            ("obj.x = 3", "(= (. obj x) 3)"),
+            ("a = b = c", "(= a (= b c))"),
+            ("a += b -= 1", "(+= a (-= b 1))"),
+            ("a = b + c * d", "(= a (+ b (* c d)))"),
+            ("a = 0..n", "(= a (.. 0 n))"),
+            ("(a) = 1", "(= a 1)"),
+        ]);
+    }
+
+    #[test]
+    fn invalid_assignments() {
+        let cases = [
+            ("f() = 3", "f()"),
+            ("1 = 2", "1"),
+            ("a + b += 1", "a + b"),
+            ("-a = 1", "-a"),
+            ("a = f() = 1", "f()"),
+        ];
+        for (src, target) in cases {
+            let res = parser(src).try_parse_expression();
+            match res {
+                Err(ParseError::InvalidAssignment(span)) => {
+                    assert_eq!(text(src, &span), target, "parsing {:?}", src)
+                }
+                res => panic!("parsing {:?} gave {:?}", src, res),
+            }
+        }
+    }
+
+    fn parse_type(src: &str) -> SpanData<TypeExpression> {
+        let mut parser = parser(src);
+        let type_expr = parser
+            .try_parse_type_expression()
+            .unwrap_or_else(|why| panic!("failed to parse {:?}: {}", src, why));
+        if let Some(token) = parser.next_token() {
+            panic!("unexpected {} after parsing {:?}", token.value, src);
+        }
+        type_expr
+    }
+
+    #[test]
+    fn type_expressions() {
+        let cases = [
+            ("Number", "Number"),
+            ("List<Number>", "List<Number>"),
+            ("Map<String, Number>", "Map<String Number>"),
+            ("Map<String, Number,>", "Map<String Number>"),
+            ("List<List<T>>", "List<List<T>>"),
+            ("Number?", "(? Number)"),
+            ("Number??", "(? (? Number))"),
+            ("List<T?>?", "(? List<(? T)>)"),
+            ("Number | String", "(| Number String)"),
+            ("A | B? | C", "(| A (? B) C)"),
+            ("(A | B)?", "(? (| A B))"),
+            ("(Number, String)", "(tuple Number String)"),
+            ("(T,)", "(tuple T)"),
+            ("(T)", "T"),
+            ("()", "(tuple )"),
+            ("fn(Number, String) -> Bool", "(fn (Number String) Bool)"),
+            ("fn()", "(fn ())"),
+            ("fn(T) -> fn(U) -> V", "(fn (T) (fn (U) V))"),
+            ("fn() -> A | B", "(fn () (| A B))"),
+            ("(fn() -> A) | B", "(| (fn () A) B)"),
+            ("List<fn(T) -> (T, U)>", "List<(fn (T) (tuple T U))>"),
+        ];
+        for (src, expected) in cases {
+            let type_expr = parse_type(src);
+            assert_eq!(show_type(&type_expr.value), expected, "parsing {:?}", src);
+            assert_eq!(text(src, &type_expr.span), src, "parsing {:?}", src);
+        }
+
+        // Deeply nested parentheses are parsed in a single pass
+        let depth = 64;
+        let src = format!("{}T{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(show_type(&parse_type(&src).value), "T");
+        let src = format!("{}T{}", "(".repeat(depth), ",)".repeat(depth));
+        let expected = format!("{}T{}", "(tuple ".repeat(depth), ")".repeat(depth));
+        assert_eq!(show_type(&parse_type(&src).value), expected);
+    }
+
+    #[test]
+    fn typed_declarations() {
+        assert_eq!(
+            parse_program("let x: Map<String, Number?> = {};\nfn f(g: fn(T) -> U, t: (T,)) {}"),
+            vec![
+                "(let x: Map<String (? Number)> (map ))",
+                "(fn f (g: (fn (T) U), t: (tuple T)) {})",
+            ]
+        );
+        for src in ["List<", "List<>", "Map<A B>", "(A", "fn(A) ->", "A |"] {
+            let res = parser(src).try_parse_type_expression();
+            assert!(res.is_err(), "parsing {:?} gave {:?}", src, res);
+        }
+    }
+
+    #[test]
+    fn unexpected_tokens() {
+        let cases: [(&str, &str, &[&str], Token); 8] = [
+            ("let = 1;", "=", &["identifier"], Token::Equals),
+            ("let x 1;", "1", &["`=`"], Token::Integer(1)),
+            ("let x = 1 2;", "2", &["`;`"], Token::Integer(2)),
+            (
+                "f(a b);",
+                "b",
+                &["`,`", "`)`"],
+                Token::Identifier("b".into()),
+            ),
+            (
+                "fn f() { a b }",
+                "b",
+                &["`;`", "`}`"],
+                Token::Identifier("b".into()),
+            ),
+            ("let x =\n  );", ")", &["expression"], Token::CloseParen),
+            (
+                "pub x;",
+                "x",
+                &["`let`", "`fn`"],
+                Token::Identifier("x".into()),
+            ),
+            (
+                "pub pub fn f() {}",
+                "pub",
+                &["`let`", "`fn`"],
+                Token::Public,
+            ),
+        ];
+        for (src, found_text, expected_names, found_token) in cases {
+            match parse_error(src) {
+                ParseError::ExpectedToken {
+                    span,
+                    expected,
+                    found,
+                } => {
+                    assert_eq!(text(src, &span), found_text, "parsing {:?}", src);
+                    let expected: Vec<_> = expected.iter().map(|name| name.as_ref()).collect();
+                    assert_eq!(expected, expected_names, "parsing {:?}", src);
+                    assert_eq!(found, found_token, "parsing {:?}", src);
+                }
+                why => panic!("parsing {:?} gave {:?}", src, why),
+            }
+        }
+
+        let why = parse_error("let x =\n  );");
+        assert_eq!(why.span().start.as_tuple(), (1, 2));
+        assert_eq!(why.to_string(), "expected expression, found `)`");
+        let why = parse_error("f(a b);");
+        assert_eq!(why.to_string(), "expected `,` or `)`, found identifier `b`");
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        let cases: [(&str, &[&str]); 6] = [
+            ("let x =", &["expression"]),
+            ("let x = 1", &["`;`"]),
+            ("f(a", &["`,`", "`)`"]),
+            ("f(a,", &["expression"]),
+            ("fn f", &["`(`"]),
+            ("pub", &["`let`", "`fn`"]),
+        ];
+        for (src, expected_names) in cases {
+            match parse_error(src) {
+                ParseError::Eof { span, expected } => {
+                    // The error points just past the final token
+                    assert_eq!(span.range(), src.len()..src.len(), "parsing {:?}", src);
+                    let expected: Vec<_> = expected.iter().map(|name| name.as_ref()).collect();
+                    assert_eq!(expected, expected_names, "parsing {:?}", src);
+                }
+                why => panic!("parsing {:?} gave {:?}", src, why),
+            }
+        }
+        assert_eq!(
+            parse_error("f(a").to_string(),
+            "expected `,` or `)`, found end of input"
+        );
+    }
+
+    #[test]
+    fn interpolated_expressions() {
+        assert_expressions(&[
+            ("\"a{x}b\"", "(str \"a\" x \"b\")"),
+            (
+                "\"Hello, {name}! You have {count + 1} items\"",
+                "(str \"Hello, \" name \"! You have \" (+ count 1) \" items\")",
+            ),
+            ("\"{f(\"{x}\")}\"", "(str (call f [(str x)]))"),
+            ("\"{{x}}\"", "\"{x}\""),
+        ]);
+
+        let src = "f(\"n = {n * 2}!\")";
+        let expr = parse_expression(src);
+        let Expression::Call(call) = &expr.value else {
+            panic!("expected a call");
+        };
+        let Expression::Interpolated(segments) = &call.args[0].value else {
+            panic!("expected an interpolated string");
+        };
+        let InterpolationSegment::Expression(product) = &segments[1] else {
+            panic!("expected an interpolated expression");
+        };
+        assert_eq!(text(src, &product.span), "n * 2");
+        let Expression::Binary(product) = &product.value else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(text(src, &product.rhs.span), "2");
+
+        for src in ["\"{}\"", "\"{a b}\"", "\"{let}\""] {
+            let res = parser(src).try_parse_expression();
+            assert!(res.is_err(), "parsing {:?} gave {:?}", src, res);
+        }
+    }
+
+    #[test]
+    fn tokens_are_pulled_lazily() {
+        let pulled = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = pulled.clone();
+        let tokens = Lexer::new("<test>", "let a = 1; f(a); let b = 2;")
+            .inspect(move |_| counter.set(counter.get() + 1));
+        let mut parser = AstParser::from_stream(tokens);
+
+        parser.try_parse_statement().unwrap();
+        assert_eq!(pulled.get(), 5);
+        parser.release_consumed();
+        assert!(parser.tokens.is_empty());
+
+        parser.try_parse_statement().unwrap();
+        assert_eq!(pulled.get(), 10);
+        parser.release_consumed();
+        assert!(parser.tokens.is_empty());
+
+        assert_eq!(parser.parse_program().unwrap().len(), 1);
+        assert_eq!(pulled.get(), 15);
+    }
+
+    #[test]
+    fn lex_errors_are_surfaced() {
+        let src = "let a = 1;\nlet b = @;";
+        match parse_error(src) {
+            ParseError::Lex(LexError::UnknownSymbol { span, .. }) => {
+                assert_eq!(span.start.as_tuple(), (1, 8))
+            }
+            why => panic!("parsing {:?} gave {:?}", src, why),
+        }
+
+        // The lex error is reported even though the parser fails first,
+        // having run out of tokens
+        assert!(matches!(parse_error("let a = 1 @"), ParseError::Lex(_)));
+        assert!(matches!(parse_error("f(\"a)"), ParseError::Lex(_)));
+
+        // A parse error before the lexer reaches the bad token is reported
+        // as is
+        assert!(matches!(
+            parse_error("let = 1; @"),
+            ParseError::ExpectedToken { .. }
+        ));
+    }
+}
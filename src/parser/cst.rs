@@ -0,0 +1,408 @@
+use std::fmt;
+
+use crate::parser::{
+    AstParser, Block, Expression, Lexer, ParseError, ParseResult, Span, SpanData, Statement, Str,
+    Trivia, TriviaToken, TypeExpression, Typed,
+};
+
+/// The kind of a node in a `SyntaxTree`, which corresponds to the AST node
+/// covering the same tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyntaxKind {
+    Program,
+
+    // Statements
+    Declaration,
+    Function,
+    ExpressionStatement,
+
+    // Expressions
+    Literal,
+    Interpolated,
+    Identifier,
+    List,
+    Map,
+    MapEntry,
+    Lambda,
+    Unary,
+    Binary,
+    Call,
+    Index,
+    Member,
+    Assign,
+    Range,
+    Block,
+    If,
+    Loop,
+    While,
+    For,
+    Break,
+    Continue,
+    Return,
+
+    Type,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(TriviaToken),
+}
+
+/// A node of the concrete syntax tree. Every token within the node, along
+/// with its trivia, is held either directly or by a descendant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    fn new(kind: SyntaxKind) -> SyntaxNode {
+        SyntaxNode {
+            kind,
+            children: Vec::new(),
+        }
+    }
+
+    /// Iterates over the child nodes, skipping tokens.
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// Iterates over every token in the node in source order, including
+    /// those held by descendants.
+    pub fn tokens(&self) -> Box<dyn Iterator<Item = &TriviaToken> + '_> {
+        Box::new(self.children.iter().flat_map(|child| match child {
+            SyntaxElement::Node(node) => node.tokens(),
+            SyntaxElement::Token(token) => Box::new(std::iter::once(token)),
+        }))
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tokens().try_for_each(|token| write!(f, "{}", token))
+    }
+}
+
+/// A lossless syntax tree, which reproduces its source exactly when
+/// displayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxTree {
+    pub root: SyntaxNode,
+    /// Trivia following the final token.
+    pub end: Vec<SpanData<Trivia>>,
+}
+
+impl SyntaxTree {
+    pub fn parse(name: impl Into<Str>, src: &str) -> ParseResult<SyntaxTree> {
+        let (tokens, end) = Lexer::new(name, src)
+            .try_parse_tokens_with_trivia()
+            .map_err(ParseError::Lex)?;
+        let program = AstParser::new(tokens.iter().map(|token| token.token.clone()).collect())
+            .parse_program()?;
+        Ok(SyntaxTree::build(&program, tokens, end))
+    }
+
+    /// Arranges `tokens` into a tree following the nodes of `program`, which
+    /// must have been parsed from the same tokens.
+    pub fn build(
+        program: &[SpanData<Statement>],
+        tokens: Vec<TriviaToken>,
+        end: Vec<SpanData<Trivia>>,
+    ) -> SyntaxTree {
+        let mut nodes = Vec::new();
+        for statement in program {
+            collect_statement(statement, &mut nodes);
+        }
+        // Parents come before their children, which share their start but
+        // stop no later
+        nodes.sort_by(|(_, a), (_, b)| {
+            a.start
+                .offset
+                .cmp(&b.start.offset)
+                .then(b.stop.offset.cmp(&a.stop.offset))
+        });
+
+        let mut stack = vec![(SyntaxNode::new(SyntaxKind::Program), usize::MAX)];
+        let mut nodes = nodes.into_iter().peekable();
+        for token in tokens {
+            let start = token.token.span.start.offset;
+            close_nodes(&mut stack, start);
+            while let Some((kind, span)) = nodes.next_if(|(_, span)| span.start.offset <= start) {
+                stack.push((SyntaxNode::new(kind), span.stop.offset));
+            }
+            let (parent, _) = stack.last_mut().expect("root node is never closed");
+            parent.children.push(SyntaxElement::Token(token));
+        }
+        close_nodes(&mut stack, usize::MAX);
+
+        let (root, _) = stack.pop().expect("root node is never closed");
+        SyntaxTree { root, end }
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        for trivia in &self.end {
+            write!(f, "{}", trivia.value.text())?;
+        }
+        Ok(())
+    }
+}
+
+/// Closes every open node which stops at or before `offset`, adding each to
+/// its parent. The root node is never closed.
+fn close_nodes(stack: &mut Vec<(SyntaxNode, usize)>, offset: usize) {
+    while stack.len() > 1 && stack[stack.len() - 1].1 <= offset {
+        let (node, _) = stack.pop().expect("stack is not empty");
+        let (parent, _) = stack.last_mut().expect("root node is never closed");
+        parent.children.push(SyntaxElement::Node(node));
+    }
+}
+
+fn collect_statement(statement: &SpanData<Statement>, out: &mut Vec<(SyntaxKind, Span)>) {
+    match &statement.value {
+        Statement::Declaration(declaration) => {
+            out.push((SyntaxKind::Declaration, statement.span.clone()));
+            collect_typed(&declaration.name, out);
+            collect_expression(&declaration.value, out);
+        }
+        Statement::Function(function) => {
+            out.push((SyntaxKind::Function, statement.span.clone()));
+            for arg in &function.args {
+                collect_typed(arg, out);
+            }
+            if let Some(return_type) = &function.return_type {
+                collect_type(return_type, out);
+            }
+            collect_block(&function.body, out);
+        }
+        Statement::Expression(expr) => {
+            out.push((SyntaxKind::ExpressionStatement, statement.span.clone()));
+            collect_expression(expr, out);
+        }
+    }
+}
+
+/// Blocks without their own span contribute only their contents.
+fn collect_block(block: &Block, out: &mut Vec<(SyntaxKind, Span)>) {
+    for statement in &block.body {
+        collect_statement(statement, out);
+    }
+    if let Some(value) = &block.value {
+        collect_expression(value, out);
+    }
+}
+
+fn collect_typed<T>(typed: &Typed<T>, out: &mut Vec<(SyntaxKind, Span)>) {
+    if let Some(type_expr) = &typed.type_expr {
+        collect_type(type_expr, out);
+    }
+}
+
+fn collect_type(type_expr: &SpanData<TypeExpression>, out: &mut Vec<(SyntaxKind, Span)>) {
+    out.push((SyntaxKind::Type, type_expr.span.clone()));
+    match &type_expr.value {
+        TypeExpression::Identifier(_) => (),
+        TypeExpression::Generic(generic) => {
+            for arg in &generic.args {
+                collect_type(arg, out);
+            }
+        }
+        TypeExpression::Optional(inner) => collect_type(inner, out),
+        TypeExpression::Union(types) | TypeExpression::Tuple(types) => {
+            for inner in types {
+                collect_type(inner, out);
+            }
+        }
+        TypeExpression::Function(function) => {
+            for arg in &function.args {
+                collect_type(arg, out);
+            }
+            if let Some(return_type) = &function.return_type {
+                collect_type(return_type, out);
+            }
+        }
+    }
+}
+
+fn collect_expression(expr: &SpanData<Expression>, out: &mut Vec<(SyntaxKind, Span)>) {
+    let kind = match &expr.value {
+        Expression::None
+        | Expression::Integer(_)
+        | Expression::Float(_)
+        | Expression::Boolean(_)
+        | Expression::String(_) => SyntaxKind::Literal,
+        Expression::Interpolated(_) => SyntaxKind::Interpolated,
+        Expression::Identifier(_) => SyntaxKind::Identifier,
+        Expression::List(_) => SyntaxKind::List,
+        Expression::Map(_) => SyntaxKind::Map,
+        Expression::Lambda(_) => SyntaxKind::Lambda,
+        Expression::Unary(_) => SyntaxKind::Unary,
+        Expression::Binary(_) => SyntaxKind::Binary,
+        Expression::Call(_) => SyntaxKind::Call,
+        Expression::Index(_) => SyntaxKind::Index,
+        Expression::Member(_) => SyntaxKind::Member,
+        Expression::Assign(_) => SyntaxKind::Assign,
+        Expression::Range(_) => SyntaxKind::Range,
+        Expression::Block(_) => SyntaxKind::Block,
+        Expression::If(_) => SyntaxKind::If,
+        Expression::Loop(_) => SyntaxKind::Loop,
+        Expression::While(_) => SyntaxKind::While,
+        Expression::For(_) => SyntaxKind::For,
+        Expression::Break(_) => SyntaxKind::Break,
+        Expression::Continue => SyntaxKind::Continue,
+        Expression::Return(_) => SyntaxKind::Return,
+    };
+    out.push((kind, expr.span.clone()));
+
+    match &expr.value {
+        Expression::None
+        | Expression::Integer(_)
+        | Expression::Float(_)
+        | Expression::Boolean(_)
+        | Expression::String(_)
+        | Expression::Identifier(_)
+        | Expression::Continue => (),
+        // Interpolated expressions lie within a single string token, so have
+        // no tokens of their own
+        Expression::Interpolated(_) => (),
+        Expression::List(items) => {
+            for item in items {
+                collect_expression(item, out);
+            }
+        }
+        Expression::Map(entries) => {
+            for entry in entries {
+                out.push((SyntaxKind::MapEntry, entry.key.span.to(&entry.value.span)));
+                collect_expression(&entry.value, out);
+            }
+        }
+        Expression::Lambda(lambda) => {
+            for arg in &lambda.args {
+                collect_typed(arg, out);
+            }
+            if let Some(return_type) = &lambda.return_type {
+                collect_type(return_type, out);
+            }
+            collect_block(&lambda.body, out);
+        }
+        Expression::Unary(unary) => collect_expression(&unary.target, out),
+        Expression::Binary(binary) => {
+            collect_expression(&binary.lhs, out);
+            collect_expression(&binary.rhs, out);
+        }
+        Expression::Call(call) => {
+            collect_expression(&call.target, out);
+            for arg in &call.args {
+                collect_expression(arg, out);
+            }
+        }
+        Expression::Index(index) => {
+            collect_expression(&index.target, out);
+            collect_expression(&index.index, out);
+        }
+        Expression::Member(member) => collect_expression(&member.target, out),
+        Expression::Assign(assign) => {
+            collect_expression(&assign.target, out);
+            collect_expression(&assign.value, out);
+        }
+        Expression::Range(range) => {
+            collect_expression(&range.start, out);
+            collect_expression(&range.stop, out);
+        }
+        Expression::Block(block) => collect_block(block, out),
+        Expression::If(if_expr) => {
+            collect_expression(&if_expr.condition, out);
+            collect_block(&if_expr.then, out);
+            if let Some(otherwise) = &if_expr.otherwise {
+                collect_block(otherwise, out);
+            }
+        }
+        Expression::Loop(loop_expr) => collect_block(&loop_expr.body, out),
+        Expression::While(while_expr) => {
+            collect_expression(&while_expr.condition, out);
+            collect_block(&while_expr.body, out);
+        }
+        Expression::For(for_expr) => {
+            collect_expression(&for_expr.iterable, out);
+            collect_block(&for_expr.body, out);
+        }
+        Expression::Break(value) | Expression::Return(value) => {
+            if let Some(value) = value {
+                collect_expression(value, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> SyntaxTree {
+        SyntaxTree::parse("<test>", src)
+            .unwrap_or_else(|why| panic!("failed to parse {:?}: {}", src, why))
+    }
+
+    #[test]
+    fn round_trip() {
+        let cases = [
+            "",
+            "   \n\t\n",
+            "// only a comment",
+            "/* block /* nested */ */\n",
+            "let x = 1;",
+            "let  x\t=  1 ;  // trailing\n\n",
+            "let x = 1;\r\nlet y = x;\r\n",
+            "/// Doc comment\npub fn f(a: int, b: List<str>?) -> int {\n    a /* inline */ + b\n}\n",
+            "let s = \"a{ b + 1 }c\";\nlet r = r#\"raw \"string\"\"#;\n",
+            "let t = \"\"\"\n    text\n    \"\"\";",
+            "let 名前 = { a: [1, 2.5e3, 0xFF], \"b\": None };",
+            "while x < 10 { x += 1; if x == 5 { break; } else { continue; } }\n// end",
+        ];
+        for src in cases {
+            assert_eq!(parse(src).to_string(), src, "round trip of {:?}", src);
+        }
+    }
+
+    #[test]
+    fn trivia_placement() {
+        let tree = parse("// a\nlet x = 1; // b\n// c\n");
+        let tokens: Vec<_> = tree.root.tokens().collect();
+        let texts = |trivia: &[SpanData<Trivia>]| -> Vec<String> {
+            trivia
+                .iter()
+                .map(|trivia| trivia.value.text().to_string())
+                .collect()
+        };
+
+        assert_eq!(tokens[0].text.as_ref(), "let");
+        assert_eq!(texts(&tokens[0].leading), vec!["// a", "\n"]);
+        assert_eq!(tokens[4].text.as_ref(), ";");
+        assert_eq!(texts(&tokens[4].trailing), vec![" ", "// b"]);
+        assert_eq!(texts(&tree.end), vec!["\n", "// c", "\n"]);
+    }
+
+    #[test]
+    fn node_structure() {
+        let tree = parse("let x = f(1) + 2;\nx;");
+        let kinds = |node: &SyntaxNode| node.nodes().map(|node| node.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds(&tree.root),
+            vec![SyntaxKind::Declaration, SyntaxKind::ExpressionStatement]
+        );
+        let declaration = tree.root.nodes().next().unwrap();
+        assert_eq!(declaration.to_string(), "let x = f(1) + 2;");
+        let binary = declaration.nodes().next().unwrap();
+        assert_eq!(binary.kind, SyntaxKind::Binary);
+        assert_eq!(kinds(binary), vec![SyntaxKind::Call, SyntaxKind::Literal]);
+    }
+}
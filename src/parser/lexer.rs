@@ -1,14 +1,30 @@
-use std::{collections::HashSet, sync::OnceLock};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::{HashSet, VecDeque},
+    fmt,
+    sync::OnceLock,
+};
 
-use regex::Regex;
+use unicode_xid::UnicodeXID;
 
-use crate::parser::{Point, PrefixTree, Span, SpanData, Str};
+use crate::parser::{FileId, LineIndex, Point, PrefixTree, SourceFile, Span, SpanData, Str};
 
-#[derive(Clone, Debug)]
+/// A piece of an interpolated string literal.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StringSegment {
+    Literal(Str),
+    /// The tokens between a pair of braces, with a span covering the braces.
+    Interpolation(SpanData<Vec<SpanData<Token>>>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Token {
-    Number(f64),
+    Integer(i64),
+    Float(f64),
     Boolean(bool),
     String(Str),
+    InterpolatedString(Vec<StringSegment>),
     None,
 
     // Word tokens
@@ -59,6 +75,78 @@ pub enum Token {
     Not,
     SingleArrow,
     DoubleArrow,
+    Pipe,
+    Question,
+
+    // Trivia tokens
+    DocComment(Str),
+
+    /// Stands in for malformed source when lexing with error recovery.
+    Error,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Integer(n) => return write!(f, "integer `{}`", n),
+            Token::Float(n) => return write!(f, "float `{}`", n),
+            Token::Boolean(true) => "True",
+            Token::Boolean(false) => "False",
+            Token::String(s) => return write!(f, "string {:?}", s),
+            Token::InterpolatedString(_) => return write!(f, "interpolated string"),
+            Token::None => "None",
+            Token::Identifier(name) => return write!(f, "identifier `{}`", name),
+            Token::Public => "pub",
+            Token::Function => "fn",
+            Token::Let => "let",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::Loop => "loop",
+            Token::While => "while",
+            Token::For => "for",
+            Token::In => "in",
+            Token::Return => "return",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::ExclusiveRange => "..",
+            Token::InclusiveRange => "..=",
+            Token::OpenBracket => "[",
+            Token::CloseBracket => "]",
+            Token::OpenBrace => "{",
+            Token::CloseBrace => "}",
+            Token::OpenParen => "(",
+            Token::CloseParen => ")",
+            Token::Period => ".",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Equals => "=",
+            Token::DoubleEquals => "==",
+            Token::Plus => "+",
+            Token::PlusEquals => "+=",
+            Token::Minus => "-",
+            Token::MinusEquals => "-=",
+            Token::Times => "*",
+            Token::TimesEquals => "*=",
+            Token::Divide => "/",
+            Token::DivideEquals => "/=",
+            Token::Modulo => "%",
+            Token::ModuloEquals => "%=",
+            Token::GreaterThan => ">",
+            Token::GreaterThanEquals => ">=",
+            Token::LessThan => "<",
+            Token::LessThanEquals => "<=",
+            Token::NotEquals => "!=",
+            Token::Not => "!",
+            Token::SingleArrow => "->",
+            Token::DoubleArrow => "=>",
+            Token::Pipe => "|",
+            Token::Question => "?",
+            Token::DocComment(_) => return write!(f, "doc comment"),
+            Token::Error => return write!(f, "invalid token"),
+        };
+        write!(f, "`{}`", text)
+    }
 }
 
 static WORD_TREE: OnceLock<PrefixTree<Token>> = OnceLock::new();
@@ -122,6 +210,8 @@ pub fn get_symbol_tree() -> &'static PrefixTree<Token> {
             ("..=", Token::InclusiveRange),
             ("->", Token::SingleArrow),
             ("=>", Token::DoubleArrow),
+            ("|", Token::Pipe),
+            ("?", Token::Question),
         ])
     })
 }
@@ -133,53 +223,138 @@ fn get_symbol_chars() -> &'static HashSet<char> {
 }
 
 fn is_atom_first_char(ch: char) -> bool {
-    ch.is_ascii_alphabetic() || ch == '_'
+    ch.is_xid_start() || ch == '_'
 }
 
 fn is_atom_char(ch: char) -> bool {
-    ch.is_ascii_digit() || is_atom_first_char(ch)
+    ch.is_xid_continue()
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum LexError {
-    Eof,
-    ExpectedNumber,
-    ExpectedAtom,
-    UnknownSymbol(Str),
-    Custom(Str),
+    Eof(Span),
+    ExpectedNumber(Span),
+    InvalidNumber(Span),
+    ExpectedAtom(Span),
+    UnknownSymbol { span: Span, symbol: Str },
+    UnterminatedComment(Span),
+    UnterminatedString(Span),
+    InvalidEscape(Span),
+    Custom { span: Span, message: Str },
 }
 
 impl LexError {
-    pub fn unknown_symbol(msg: impl Into<Str>) -> LexError {
-        LexError::UnknownSymbol(msg.into())
+    pub fn unknown_symbol(span: Span, symbol: impl Into<Str>) -> LexError {
+        LexError::UnknownSymbol {
+            span,
+            symbol: symbol.into(),
+        }
     }
 
-    pub fn custom(msg: impl Into<Str>) -> LexError {
-        LexError::Custom(msg.into())
+    pub fn custom(span: Span, msg: impl Into<Str>) -> LexError {
+        LexError::Custom {
+            span,
+            message: msg.into(),
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            LexError::Eof(span)
+            | LexError::ExpectedNumber(span)
+            | LexError::InvalidNumber(span)
+            | LexError::ExpectedAtom(span)
+            | LexError::UnknownSymbol { span, .. }
+            | LexError::UnterminatedComment(span)
+            | LexError::UnterminatedString(span)
+            | LexError::InvalidEscape(span)
+            | LexError::Custom { span, .. } => span,
+        }
     }
 }
 
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::Eof(_) => write!(f, "unexpected end of input"),
+            LexError::ExpectedNumber(_) => write!(f, "expected number"),
+            LexError::InvalidNumber(_) => write!(f, "invalid number literal"),
+            LexError::ExpectedAtom(_) => write!(f, "expected identifier or keyword"),
+            LexError::UnknownSymbol { symbol, .. } => write!(f, "unknown symbol `{}`", symbol),
+            LexError::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+            LexError::UnterminatedString(_) => write!(f, "unterminated string literal"),
+            LexError::InvalidEscape(_) => write!(f, "invalid escape sequence"),
+            LexError::Custom { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
 pub type LexResult<T> = Result<T, LexError>;
 
-pub struct Lexer {
-    lines: Vec<Vec<char>>,
-    pos: Point,
-    name: Str,
+/// Source text which is skipped between tokens, holding its exact text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trivia {
+    Whitespace(Str),
+    LineComment(Str),
+    BlockComment(Str),
 }
 
-static IDENTIFIER_REGEX: OnceLock<Regex> = OnceLock::new();
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Trivia::Whitespace(text) | Trivia::LineComment(text) | Trivia::BlockComment(text) => {
+                text
+            }
+        }
+    }
+}
 
-fn get_identifier_regex() -> &'static Regex {
-    IDENTIFIER_REGEX.get_or_init(|| {
-        Regex::new(r"[a-zA-Z_][a-zA-Z0-9_]*").expect("failed to compile identifier regex")
-    })
+/// A token along with its exact source text and the trivia around it, which
+/// together reproduce the source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TriviaToken {
+    pub token: SpanData<Token>,
+    pub text: Str,
+    /// Trivia between the previous token's trailing trivia and this token.
+    pub leading: Vec<SpanData<Trivia>>,
+    /// Trivia following the token on the same line.
+    pub trailing: Vec<SpanData<Trivia>>,
 }
 
-static NUMBER_REGEX: OnceLock<Regex> = OnceLock::new();
+impl fmt::Display for TriviaToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in &self.leading {
+            write!(f, "{}", trivia.value.text())?;
+        }
+        write!(f, "{}", self.text)?;
+        for trivia in &self.trailing {
+            write!(f, "{}", trivia.value.text())?;
+        }
+        Ok(())
+    }
+}
 
-fn get_number_regex() -> &'static Regex {
-    NUMBER_REGEX
-        .get_or_init(|| Regex::new(r"[0-9]*(\.[0-9]+)?").expect("failed to compile number regex"))
+/// Scans a borrowed source string by byte offset. Rows and columns are only
+/// computed when a span is produced, using the offsets at which each line
+/// begins.
+pub struct Lexer<'src> {
+    src: &'src str,
+    lines: Cow<'src, LineIndex>,
+    /// The most recently computed `Point`. The next point is usually close
+    /// to it on the same line, so its column can be found by counting from
+    /// here rather than from the start of the line, which would make lexing
+    /// quadratic in the length of a line.
+    last_point: Cell<Point>,
+    offset: usize,
+    name: Str,
+    file: Option<FileId>,
+    /// Tokens which have been lexed by `peek_nth` but not yet yielded.
+    lookahead: VecDeque<LexResult<SpanData<Token>>>,
+    /// Set once the end of input or an error has been reached, after which no
+    /// further tokens are produced.
+    finished: bool,
 }
 
 fn is_whitespace(ch: char) -> bool {
@@ -194,65 +369,128 @@ fn is_numeric(ch: char) -> bool {
     ch.is_ascii_digit()
 }
 
-pub fn split_lines(src: &str) -> Vec<Vec<char>> {
-    let mut lines = Vec::new();
-    let mut line = Vec::new();
-    for ch in src.chars() {
-        line.push(ch);
-        if ch == '\n' {
-            lines.push(line);
-            line = Vec::new();
-        }
+/// Finds the byte range of each line of a multi-line string once the leading
+/// whitespace shared by every non-blank line is removed. A blank first or
+/// last line, such as the line breaks directly inside the delimiters, is
+/// dropped entirely. Line ranges exclude the `\r` of a CRLF line break.
+fn strip_indentation(text: &str) -> Vec<std::ops::Range<usize>> {
+    let mut lines: Vec<(usize, &str)> = Vec::new();
+    let mut start = 0;
+    for line in text.split('\n') {
+        lines.push((start, line.strip_suffix('\r').unwrap_or(line)));
+        start += line.len() + 1;
     }
-    lines.push(line);
+    if lines.len() > 1 && lines[0].1.trim().is_empty() {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && lines[lines.len() - 1].1.trim().is_empty() {
+        lines.pop();
+    }
+
+    let indent = lines
+        .iter()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(_, line)| line.chars().take_while(|ch| is_whitespace(*ch)).count())
+        .min()
+        .unwrap_or(0);
+
     lines
+        .iter()
+        .map(|&(start, line)| {
+            let offset = match line.char_indices().nth(indent) {
+                Some((offset, _)) => offset,
+                None => line.len() - line.trim_start().len(),
+            };
+            start + offset..start + line.len()
+        })
+        .collect()
 }
 
-impl Lexer {
-    pub fn new(name: impl Into<Str>, src: &str) -> Lexer {
+impl<'src> Lexer<'src> {
+    pub fn new(name: impl Into<Str>, src: &'src str) -> Lexer<'src> {
         Lexer {
-            lines: split_lines(src),
-            pos: (0, 0).into(),
+            src,
+            lines: Cow::Owned(LineIndex::new(src)),
+            last_point: Cell::new(Point::default()),
+            offset: 0,
             name: name.into(),
+            file: None,
+            lookahead: VecDeque::new(),
+            finished: false,
         }
     }
 
-    fn get_char(&self) -> LexResult<char> {
-        let (row, col) = self.pos.as_tuple();
-        self.lines
-            .get(row)
-            .and_then(|line| line.get(col))
-            .cloned()
-            .ok_or_else(|| LexError::Eof)
+    /// Creates a lexer over a file in a `SourceMap`, whose spans refer back to
+    /// the file.
+    pub fn for_file(file: &'src SourceFile) -> Lexer<'src> {
+        Lexer {
+            src: file.src(),
+            lines: Cow::Borrowed(file.lines()),
+            last_point: Cell::new(Point::default()),
+            offset: 0,
+            name: file.name().clone(),
+            file: Some(file.id()),
+            lookahead: VecDeque::new(),
+            finished: false,
+        }
     }
 
-    fn decrement_pos(&mut self) {
-        // If we are beyond the final line, move to last char
-        if self.pos.row >= self.lines.len() {
-            self.pos.row = self.lines.len() - 1;
-            self.pos.col = self.lines[self.pos.row].len() - 1;
-            return;
-        }
+    /// The source which has not yet been consumed.
+    fn rest(&self) -> &'src str {
+        &self.src[self.offset..]
+    }
 
-        // Decrement line if we reach beginning of a line
-        if self.pos.col == 0 {
-            self.pos.row -= 1;
-            self.pos.col = self.lines[self.pos.row].len() - 1;
-            return;
+    /// Converts a byte offset into the source to a `Point`.
+    fn point_at(&self, offset: usize) -> Point {
+        let last = self.last_point.take();
+        let row = self.lines.row_near(offset, last.row);
+        let point = if row != last.row {
+            self.lines.point(self.src, offset, row)
+        } else if offset >= last.offset {
+            Point {
+                row,
+                col: last.col + self.src[last.offset..offset].chars().count(),
+                offset,
+            }
+        } else {
+            Point {
+                row,
+                col: last.col - self.src[offset..last.offset].chars().count(),
+                offset,
+            }
+        };
+        self.last_point.set(point.clone());
+        point
+    }
+
+    /// A span running from the byte offset `start` to the current position.
+    fn span_from(&self, start: usize) -> Span {
+        self.span_between(start, self.offset)
+    }
+
+    fn span_between(&self, start: usize, stop: usize) -> Span {
+        Span {
+            name: self.name.clone(),
+            file: self.file,
+            start: self.point_at(start),
+            stop: self.point_at(stop),
         }
+    }
+
+    fn empty_span(&self) -> Span {
+        self.span_from(self.offset)
+    }
 
-        // Otherwise just decrement column
-        self.pos.col -= 1;
+    fn get_char(&self) -> LexResult<char> {
+        self.rest()
+            .chars()
+            .next()
+            .ok_or_else(|| LexError::Eof(self.empty_span()))
     }
 
     fn advance_pos(&mut self) -> Option<()> {
-        let line = self.lines.get(self.pos.row)?;
-        if self.pos.col >= line.len() - 1 {
-            self.pos.col = 0;
-            self.pos.row += 1;
-        } else {
-            self.pos.col += 1;
-        }
+        let ch = self.rest().chars().next()?;
+        self.offset += ch.len_utf8();
         Some(())
     }
 
@@ -262,219 +500,1468 @@ impl Lexer {
         Ok(ch)
     }
 
-    fn try_run<T>(&mut self, func: impl Fn(&mut Lexer) -> LexResult<T>) -> LexResult<T> {
-        let start_pos = self.pos.clone();
+    fn try_run<T>(&mut self, func: impl Fn(&mut Self) -> LexResult<T>) -> LexResult<T> {
+        let start = self.offset;
 
         let res = func(self);
 
         // Reset if there was an error
         if res.is_err() {
-            self.pos = start_pos;
+            self.offset = start;
         }
 
         res
     }
 
-    fn read_while_to(&mut self, predicate: impl Fn(char) -> bool, buf: &mut SpanData<String>) {
-        while let Ok(ch) = self.next_char() {
-            if !predicate(ch) {
-                self.decrement_pos();
-                break;
-            }
-            buf.value.push(ch);
-        }
-
-        let stop = self.pos.clone();
-        buf.span.stop = stop;
-    }
-
-    fn read_while(&mut self, predicate: impl Fn(char) -> bool) -> SpanData<String> {
-        let mut res = SpanData {
-            span: Span {
-                name: self.name.clone(),
-                start: self.pos.clone(),
-                stop: self.pos.clone(),
-            },
-            value: String::new(),
-        };
-        self.read_while_to(predicate, &mut res);
-        res
+    fn read_while(&mut self, predicate: impl Fn(char) -> bool) -> &'src str {
+        let rest = self.rest();
+        let len = rest.find(|ch| !predicate(ch)).unwrap_or(rest.len());
+        self.offset += len;
+        &rest[..len]
     }
 
+    /// Parses a string literal, processing escape sequences and
+    /// interpolations. `{{` and `}}` stand for literal braces, while a lone
+    /// `}` is an error.
     fn try_parse_string(&mut self) -> LexResult<SpanData<Token>> {
         self.try_run(|lexer| {
-            let start = lexer.pos.clone();
+            let start = lexer.offset;
             lexer.try_parse_char(|ch| ch == '"')?;
-            let mut buf = String::new();
+            let quote = lexer.span_from(start);
 
-            let mut prev_ch = '"';
-            while let Ok(ch) = lexer.next_char() {
-                match (prev_ch, ch) {
-                    ('\\', ch) => {
-                        buf.push(ch);
+            let mut segments = Vec::new();
+            let mut buf = String::new();
+            loop {
+                let segment_start = lexer.offset;
+                match lexer.next_char() {
+                    Ok('"') => break,
+                    Ok('\\') => buf.push(lexer.parse_escape(segment_start, &quote)?),
+                    Ok('{') if lexer.starts_with("{") => {
+                        lexer.advance_pos();
+                        buf.push('{');
+                    }
+                    Ok('}') if lexer.starts_with("}") => {
+                        lexer.advance_pos();
+                        buf.push('}');
                     }
-                    (_, '"') => {
-                        break;
+                    Ok('}') => {
+                        return Err(LexError::custom(
+                            lexer.span_from(segment_start),
+                            "unmatched `}` in string literal, use `}}` for a literal brace",
+                        ))
                     }
-                    (_, ch) => {
-                        buf.push(ch);
+                    Ok('{') => {
+                        if !buf.is_empty() {
+                            segments.push(StringSegment::Literal(buf.into()));
+                            buf = String::new();
+                        }
+                        let tokens = lexer.parse_interpolation(segment_start, &quote)?;
+                        segments.push(StringSegment::Interpolation(tokens));
                     }
+                    Ok(ch) => buf.push(ch),
+                    Err(_) => return Err(LexError::UnterminatedString(quote)),
                 }
-                prev_ch = ch;
             }
 
-            let mut span = lexer.empty_span();
-            span.start = start;
+            let token = if segments.is_empty() {
+                Token::String(buf.into())
+            } else {
+                if !buf.is_empty() {
+                    segments.push(StringSegment::Literal(buf.into()));
+                }
+                Token::InterpolatedString(segments)
+            };
+
             Ok(SpanData {
-                span,
-                value: Token::String(buf.into()),
+                span: lexer.span_from(start),
+                value: token,
             })
         })
     }
 
-    fn try_parse_symbol(&mut self) -> LexResult<SpanData<Token>> {
+    /// Whether the upcoming characters begin a raw string such as `r"..."` or
+    /// `r#"..."#`.
+    fn is_raw_string_start(&self) -> bool {
+        self.starts_with("r\"") || self.starts_with("r#")
+    }
+
+    /// Parses a raw string, in which backslashes have no special meaning. Any
+    /// number of `#` may surround the quotes, allowing the string to contain
+    /// `"` followed by fewer `#`.
+    fn try_parse_raw_string(&mut self) -> LexResult<SpanData<Token>> {
         self.try_run(|lexer| {
-            let tree = get_symbol_tree();
-            let symbols = get_symbol_chars();
-            let symbol_text = lexer.read_while(|ch| symbols.contains(&ch));
-            let symbol_token = tree
-                .find(&symbol_text.value)
-                .cloned()
-                .ok_or_else(|| LexError::unknown_symbol(symbol_text.value.as_str()))?;
+            let start = lexer.offset;
+            lexer.try_parse_char(|ch| ch == 'r')?;
+            let hashes = lexer.read_while(|ch| ch == '#').len();
+            lexer.try_parse_char(|ch| ch == '"').map_err(|_| {
+                LexError::custom(lexer.span_from(start), "expected `\"` to begin raw string")
+            })?;
+            let open = lexer.span_from(start);
+
+            let close = format!("\"{}", "#".repeat(hashes));
+            let len = lexer
+                .rest()
+                .find(&close)
+                .ok_or(LexError::UnterminatedString(open))?;
+            let contents = &lexer.rest()[..len];
+            lexer.offset += len + close.len();
+
             Ok(SpanData {
-                span: symbol_text.span,
-                value: symbol_token,
+                span: lexer.span_from(start),
+                value: Token::String(contents.into()),
             })
         })
     }
 
-    fn empty_span(&self) -> Span {
-        Span {
-            name: self.name.clone(),
-            start: self.pos.clone(),
-            stop: self.pos.clone(),
+    /// The length of a triple quoted string's contents, up to its closing
+    /// `"""`. Escaped characters are skipped, so `\"""` does not close it.
+    fn find_multiline_close(&self) -> Option<usize> {
+        let rest = self.rest();
+        let mut chars = rest.char_indices();
+        while let Some((offset, ch)) = chars.next() {
+            match ch {
+                '\\' => {
+                    chars.next();
+                }
+                '"' if rest[offset..].starts_with("\"\"\"") => return Some(offset),
+                _ => (),
+            }
         }
+        None
+    }
+
+    /// Parses a triple quoted string, which may span multiple lines. The
+    /// indentation common to every line is removed first, after which escape
+    /// sequences are processed as in any other string. Interpolation is not
+    /// supported, so braces are taken literally.
+    fn try_parse_multiline_string(&mut self) -> LexResult<SpanData<Token>> {
+        self.try_run(|lexer| {
+            let start = lexer.offset;
+            for _ in 0..3 {
+                lexer.try_parse_char(|ch| ch == '"')?;
+            }
+            let open = lexer.span_from(start);
+
+            let contents_start = lexer.offset;
+            let len = lexer
+                .find_multiline_close()
+                .ok_or_else(|| LexError::UnterminatedString(open.clone()))?;
+
+            let mut value = String::new();
+            for (i, line) in strip_indentation(&lexer.rest()[..len])
+                .into_iter()
+                .enumerate()
+            {
+                if i > 0 {
+                    value.push('\n');
+                }
+                lexer.offset = contents_start + line.start;
+                while lexer.offset < contents_start + line.end {
+                    let char_start = lexer.offset;
+                    match lexer.next_char()? {
+                        '\\' => value.push(lexer.parse_escape(char_start, &open)?),
+                        ch => value.push(ch),
+                    }
+                }
+            }
+            lexer.offset = contents_start + len + 3;
+
+            Ok(SpanData {
+                span: lexer.span_from(start),
+                value: Token::String(value.into()),
+            })
+        })
+    }
+
+    /// Lexes the tokens of an interpolated expression whose opening brace
+    /// began at the byte offset `start`, up to and including the matching
+    /// closing brace.
+    fn parse_interpolation(
+        &mut self,
+        start: usize,
+        quote: &Span,
+    ) -> LexResult<SpanData<Vec<SpanData<Token>>>> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            // A line comment would swallow the closing brace and quote, so
+            // only block comments are allowed within an interpolation
+            loop {
+                self.skip_whitespace();
+                if self.starts_with("//") {
+                    return Err(LexError::custom(
+                        self.span_between(self.offset, self.offset + 2),
+                        "line comments are not allowed in string interpolations",
+                    ));
+                } else if self.starts_with("/*") {
+                    self.skip_block_comment()?;
+                } else {
+                    break;
+                }
+            }
+
+            // The closing brace is read directly, as the string may continue
+            // with characters that would otherwise lex as part of a symbol
+            if depth == 0 && self.starts_with("}") {
+                self.advance_pos();
+                return Ok(SpanData {
+                    span: self.span_from(start),
+                    value: tokens,
+                });
+            }
+
+            // A string within the interpolation which runs to the end of the
+            // input, as in `"{" x`, leaves the enclosing string unterminated
+            let token = match self.next_token() {
+                Ok(Some(token)) => token,
+                Ok(None) | Err(LexError::UnterminatedString(_)) => {
+                    return Err(LexError::UnterminatedString(quote.clone()))
+                }
+                Err(why) => return Err(why),
+            };
+            match token.value {
+                Token::OpenBrace => depth += 1,
+                Token::CloseBrace => depth -= 1,
+                _ => (),
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Parses the remainder of an escape sequence whose backslash began at the
+    /// byte offset `start`. `quote` is the opening quote of the enclosing
+    /// string.
+    fn parse_escape(&mut self, start: usize, quote: &Span) -> LexResult<char> {
+        let ch = self
+            .next_char()
+            .map_err(|_| LexError::UnterminatedString(quote.clone()))?;
+        let escaped = match ch {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            'u' => self.parse_unicode_escape(),
+            _ => None,
+        };
+
+        escaped.ok_or_else(|| LexError::InvalidEscape(self.span_from(start)))
+    }
+
+    /// Parses the `{XXXX}` portion of a `\u{XXXX}` escape, consisting of one to
+    /// six hexadecimal digits naming a Unicode scalar value.
+    fn parse_unicode_escape(&mut self) -> Option<char> {
+        self.try_parse_char(|ch| ch == '{').ok()?;
+        let digits = self.read_while(|ch| ch.is_ascii_hexdigit());
+        self.try_parse_char(|ch| ch == '}').ok()?;
+        if digits.is_empty() || digits.len() > 6 {
+            return None;
+        }
+        let code = u32::from_str_radix(digits, 16).ok()?;
+        char::from_u32(code)
+    }
+
+    /// Parses the longest symbol at the current position, so that adjacent
+    /// symbols such as `();` or `=-` are split correctly.
+    fn try_parse_symbol(&mut self) -> LexResult<SpanData<Token>> {
+        self.try_run(|lexer| {
+            let start = lexer.offset;
+            match get_symbol_tree().longest_match(lexer.rest()) {
+                Some((symbol, token)) => {
+                    lexer.offset += symbol.len();
+                    Ok(SpanData {
+                        span: lexer.span_from(start),
+                        value: token.clone(),
+                    })
+                }
+                None => {
+                    // Report the whole run of characters which cannot begin
+                    // any token
+                    let symbols = get_symbol_chars();
+                    lexer.advance_pos();
+                    lexer.read_while(|ch| {
+                        !is_whitespace(ch)
+                            && !is_atom_char(ch)
+                            && !symbols.contains(&ch)
+                            && ch != '"'
+                    });
+                    Err(LexError::unknown_symbol(
+                        lexer.span_from(start),
+                        &lexer.src[start..lexer.offset],
+                    ))
+                }
+            }
+        })
     }
 
     fn skip_whitespace(&mut self) {
-        while let Ok(ch) = self.next_char() {
-            if !is_whitespace(ch) {
-                self.decrement_pos();
-                break;
+        self.read_while(is_whitespace);
+    }
+
+    /// Whether the upcoming characters match `prefix`, without consuming them.
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.rest().starts_with(prefix)
+    }
+
+    /// `///` begins a doc comment, but four or more slashes do not.
+    fn is_doc_comment_start(&self) -> bool {
+        self.starts_with("///") && !self.starts_with("////")
+    }
+
+    fn skip_line(&mut self) {
+        self.read_while(|ch| ch != '\n');
+    }
+
+    /// Skips a block comment, which may contain nested block comments. An
+    /// unterminated comment is reported at its opening `/*`.
+    fn skip_block_comment(&mut self) -> LexResult<()> {
+        let start = self.offset;
+        let mut depth = 0;
+        loop {
+            if self.starts_with("/*") {
+                self.skip_chars(2);
+                depth += 1;
+            } else if self.starts_with("*/") {
+                self.skip_chars(2);
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if self.next_char().is_err() {
+                let open = self.span_between(start, start + "/*".len());
+                return Err(LexError::UnterminatedComment(open));
+            }
+        }
+    }
+
+    fn skip_chars(&mut self, count: usize) {
+        for _ in 0..count {
+            self.advance_pos();
+        }
+    }
+
+    /// Skips whitespace along with line and block comments. Doc comments are
+    /// left in place to be read as tokens.
+    fn skip_trivia(&mut self) -> LexResult<()> {
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("//") && !self.is_doc_comment_start() {
+                self.skip_line();
+            } else if self.starts_with("/*") {
+                self.skip_block_comment()?;
+            } else {
+                return Ok(());
             }
         }
     }
 
+    /// Reads whitespace and comments as trivia. When `same_line` is set, the
+    /// trivia stops before the next line break.
+    fn read_trivia(&mut self, same_line: bool) -> LexResult<Vec<SpanData<Trivia>>> {
+        let mut trivia = Vec::new();
+        loop {
+            let start = self.offset;
+            let value = if self.starts_with("//") && !self.is_doc_comment_start() {
+                self.skip_line();
+                Trivia::LineComment(self.src[start..self.offset].into())
+            } else if self.starts_with("/*") {
+                self.skip_block_comment()?;
+                Trivia::BlockComment(self.src[start..self.offset].into())
+            } else {
+                let text = self.read_while(|ch| is_whitespace(ch) && !(same_line && ch == '\n'));
+                if text.is_empty() {
+                    return Ok(trivia);
+                }
+                Trivia::Whitespace(text.into())
+            };
+            trivia.push(SpanData {
+                span: self.span_from(start),
+                value,
+            });
+        }
+    }
+
+    fn try_parse_doc_comment(&mut self) -> LexResult<SpanData<Token>> {
+        if !self.is_doc_comment_start() {
+            return Err(LexError::custom(self.empty_span(), "expected doc comment"));
+        }
+        let start = self.offset;
+        let text = self.read_while(|ch| ch != '\n');
+        let content = text["///".len()..].trim_end_matches('\r');
+        let content = content.strip_prefix(' ').unwrap_or(content);
+        Ok(SpanData {
+            value: Token::DocComment(content.into()),
+            span: self.span_from(start),
+        })
+    }
+
     fn try_parse_atom(&mut self) -> LexResult<SpanData<Token>> {
         self.try_run(|lexer| {
-            // Create empty SpanData to read into
-            let mut res = SpanData {
-                span: lexer.empty_span(),
-                value: String::new(),
-            };
+            let start = lexer.offset;
 
             // Check first character
-            let first_char = lexer
+            lexer
                 .try_parse_char(is_atom_first_char)
-                .map_err(|_| LexError::ExpectedAtom)?;
-            res.value.push(first_char);
-            res.span.stop = lexer.pos.clone();
+                .map_err(|_| LexError::ExpectedAtom(lexer.empty_span()))?;
 
             // Read all additional characters
-            lexer.read_while_to(is_atom_char, &mut res);
+            lexer.read_while(is_atom_char);
+            let text = &lexer.src[start..lexer.offset];
 
             // Check if it's a word token
-            let token = match get_word_tree().find(&res.value) {
+            let token = match get_word_tree().find(text) {
                 Some(token) => token.clone(),
-                _ => Token::Identifier(res.value.into()),
+                _ => Token::Identifier(text.into()),
             };
 
             Ok(SpanData {
-                span: res.span,
+                span: lexer.span_from(start),
                 value: token,
             })
         })
     }
 
     fn try_parse_char(&mut self, predicate: impl Fn(char) -> bool) -> LexResult<char> {
-        let ch = self.next_char()?;
+        let ch = self.get_char()?;
         if predicate(ch) {
+            self.advance_pos();
             Ok(ch)
         } else {
-            self.decrement_pos();
-            Err(LexError::custom("character failed predicate"))
+            Err(LexError::custom(
+                self.empty_span(),
+                "character failed predicate",
+            ))
         }
     }
 
-    fn try_parse_number(&mut self) -> LexResult<SpanData<f64>> {
-        self.try_run(|lexer| {
-            let mut number = lexer.read_while(is_numeric);
-            match lexer.next_char() {
-                Ok('.') => {
-                    let post_decimal = lexer.read_while(is_numeric);
-
-                    // Check if we find any numbers after the .
-                    if !post_decimal.value.is_empty() {
-                        // If we didn't, then the . was not part of a decimal number
-                        lexer.decrement_pos();
-                        Ok(())
-                    } else {
-                        number.value.push('.');
-                        number.value.push_str(&post_decimal.value);
-                        number.span.stop = post_decimal.span.stop;
-                        Ok(())
-                    }
-                }
-                Ok(_) => {
-                    lexer.decrement_pos();
-                    Ok(())
-                }
-                Err(_) if !number.value.is_empty() => Ok(()),
-                Err(why) => Err(why),
-            }?;
-            let number_value: f64 = number.value.parse().map_err(|_| LexError::ExpectedNumber)?;
-            Ok(SpanData {
-                span: number.span,
-                value: number_value,
-            })
-        })
+    /// Looks ahead `offset` characters past the current character without
+    /// consuming anything.
+    fn peek_char(&self, offset: usize) -> Option<char> {
+        self.rest().chars().nth(offset)
+    }
+
+    /// Consumes the remainder of a malformed number literal beginning at the
+    /// byte offset `start`, producing an error spanning all of it.
+    fn invalid_number(&mut self, start: usize) -> LexError {
+        self.read_while(is_atom_char);
+        LexError::InvalidNumber(self.span_from(start))
+    }
+
+    /// Reads a run of digits in the given radix, which may be separated by
+    /// single underscores. The digits are returned with the separators
+    /// removed, or `None` if there are no digits or a separator is misplaced.
+    fn read_digits(&mut self, radix: u32) -> Option<String> {
+        let digits = self.read_while(|ch| ch.is_digit(radix) || ch == '_');
+        let is_valid = !digits.is_empty()
+            && !digits.starts_with('_')
+            && !digits.ends_with('_')
+            && !digits.contains("__");
+        is_valid.then(|| digits.replace('_', ""))
+    }
+
+    /// Parses a decimal literal, which is a float if it has a fractional part
+    /// or an exponent and an integer otherwise. A `.` is only part of the
+    /// literal when followed by a digit, so `0..n` and `3.abs()` are left to
+    /// be lexed as symbols.
+    fn parse_decimal(&mut self, start: usize) -> LexResult<Token> {
+        let mut text = self
+            .read_digits(10)
+            .ok_or_else(|| self.invalid_number(start))?;
+        let mut is_float = false;
+
+        if self.starts_with(".") && self.peek_char(1).map(is_numeric).unwrap_or(false) {
+            self.advance_pos();
+            let fraction = self
+                .read_digits(10)
+                .ok_or_else(|| self.invalid_number(start))?;
+            text.push('.');
+            text.push_str(&fraction);
+            is_float = true;
+        }
+
+        if self.starts_with("e") || self.starts_with("E") {
+            self.advance_pos();
+            text.push('e');
+            if let Ok(sign @ ('+' | '-')) = self.get_char() {
+                self.advance_pos();
+                text.push(sign);
+            }
+            let exponent = self
+                .read_digits(10)
+                .ok_or_else(|| self.invalid_number(start))?;
+            text.push_str(&exponent);
+            is_float = true;
+        }
+
+        if is_float {
+            // Like integer overflow, a float too large to represent is invalid
+            // rather than infinite
+            let value: f64 = text.parse().map_err(|_| self.invalid_number(start))?;
+            if !value.is_finite() {
+                return Err(self.invalid_number(start));
+            }
+            Ok(Token::Float(value))
+        } else {
+            let value = text.parse().map_err(|_| self.invalid_number(start))?;
+            Ok(Token::Integer(value))
+        }
     }
 
+    /// Parses an integer or float literal. Integers may also be written in
+    /// hexadecimal, octal or binary with a `0x`, `0o` or `0b` prefix.
     fn try_parse_number_token(&mut self) -> LexResult<SpanData<Token>> {
         self.try_run(|lexer| {
-            let num = lexer.try_parse_number()?;
+            let start = lexer.offset;
+            if !lexer.get_char().map(is_numeric).unwrap_or(false) {
+                return Err(LexError::ExpectedNumber(lexer.empty_span()));
+            }
+
+            let radix = [("0x", 16), ("0o", 8), ("0b", 2)]
+                .into_iter()
+                .find(|(prefix, _)| lexer.starts_with(prefix))
+                .map(|(_, radix)| radix);
+            let token = match radix {
+                Some(radix) => {
+                    lexer.skip_chars(2);
+                    let digits = lexer
+                        .read_digits(radix)
+                        .ok_or_else(|| lexer.invalid_number(start))?;
+                    let value = i64::from_str_radix(&digits, radix)
+                        .map_err(|_| lexer.invalid_number(start))?;
+                    Token::Integer(value)
+                }
+                None => lexer.parse_decimal(start)?,
+            };
+
+            // A literal running straight into letters, such as `0xFG` or
+            // `12ab`, is malformed
+            if lexer.get_char().map(is_atom_char).unwrap_or(false) {
+                return Err(lexer.invalid_number(start));
+            }
+
             Ok(SpanData {
-                span: num.span,
-                value: Token::Number(num.value),
+                span: lexer.span_from(start),
+                value: token,
             })
         })
     }
 
     fn is_done(&self) -> bool {
-        self.get_char().is_err()
+        self.offset >= self.src.len()
     }
 
     fn next_token(&mut self) -> LexResult<Option<SpanData<Token>>> {
-        self.skip_whitespace();
+        self.skip_trivia()?;
         if !self.is_done() {
-            let token = self
-                .try_parse_atom()
-                .or_else(|_| self.try_parse_string())
-                .or_else(|_| self.try_parse_number_token())
-                .or_else(|_| self.try_parse_symbol())?;
+            // Strings and numbers report their own errors rather than falling
+            // back to other token kinds
+            let token = if self.starts_with("\"\"\"") {
+                self.try_parse_multiline_string()?
+            } else if self.starts_with("\"") {
+                self.try_parse_string()?
+            } else if self.is_raw_string_start() {
+                self.try_parse_raw_string()?
+            } else if self.get_char().map(is_numeric).unwrap_or(false) {
+                self.try_parse_number_token()?
+            } else if self.is_doc_comment_start() {
+                self.try_parse_doc_comment()?
+            } else if self.get_char().map(is_atom_first_char).unwrap_or(false) {
+                self.try_parse_atom()?
+            } else {
+                self.try_parse_symbol()?
+            };
             Ok(Some(token))
         } else {
             Ok(None)
         }
     }
 
+    /// Lexes the next token from the source, stopping for good after the end
+    /// of input or the first error.
+    fn lex_next(&mut self) -> Option<LexResult<SpanData<Token>>> {
+        if self.finished {
+            return None;
+        }
+        let res = self.next_token().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            self.finished = true;
+        }
+        res
+    }
+
+    /// Looks ahead `n` tokens past the next one without consuming anything.
+    pub fn peek_nth(&mut self, n: usize) -> Option<&LexResult<SpanData<Token>>> {
+        while self.lookahead.len() <= n {
+            let next = self.lex_next()?;
+            self.lookahead.push_back(next);
+        }
+        self.lookahead.get(n)
+    }
+
+    pub fn peek(&mut self) -> Option<&LexResult<SpanData<Token>>> {
+        self.peek_nth(0)
+    }
+
     pub fn try_parse_tokens(&mut self) -> LexResult<Vec<SpanData<Token>>> {
-        let mut out = Vec::new();
+        self.collect()
+    }
+
+    /// Lexes the entire source while keeping the whitespace and comments
+    /// around each token. The trivia following the final token's line is
+    /// returned separately.
+    pub fn try_parse_tokens_with_trivia(
+        &mut self,
+    ) -> LexResult<(Vec<TriviaToken>, Vec<SpanData<Trivia>>)> {
+        let mut tokens = Vec::new();
+        loop {
+            let leading = self.read_trivia(false)?;
+            let start = self.offset;
+            let token = match self.next_token()? {
+                Some(token) => token,
+                None => return Ok((tokens, leading)),
+            };
+            let text = self.src[start..self.offset].into();
+            let trailing = self.read_trivia(true)?;
+            tokens.push(TriviaToken {
+                token,
+                text,
+                leading,
+                trailing,
+            });
+        }
+    }
+
+    /// Lexes the entire source, continuing past errors rather than stopping
+    /// at the first one. Each malformed region is replaced with a
+    /// `Token::Error`, and every error encountered is returned alongside the
+    /// tokens.
+    pub fn parse_tokens_recovering(&mut self) -> (Vec<SpanData<Token>>, Vec<LexError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            // An unterminated block comment runs to the end of the input, so
+            // there is nothing left to replace
+            if let Err(why) = self.skip_trivia() {
+                errors.push(why);
+                continue;
+            }
+
+            let start = self.offset;
+            match self.next_token() {
+                Ok(Some(token)) => tokens.push(token),
+                Ok(None) => break,
+                Err(why) => {
+                    self.offset = start;
+                    self.skip_malformed(&why);
+                    tokens.push(SpanData {
+                        span: self.span_from(start),
+                        value: Token::Error,
+                    });
+                    errors.push(why);
+                }
+            }
+        }
+        (tokens, errors)
+    }
+
+    /// Skips past the malformed token which caused `why`, so that lexing may
+    /// resume at the next plausible token boundary. At least one character
+    /// is always skipped.
+    fn skip_malformed(&mut self, why: &LexError) {
+        let start = self.offset;
+        if matches!(why, LexError::UnterminatedString(_)) {
+            self.offset = self.src.len();
+        } else if self.starts_with("\"\"\"") {
+            self.skip_chars(3);
+            let len = self.find_multiline_close().unwrap_or(self.rest().len());
+            self.offset += len;
+            self.skip_chars(3);
+        } else if self.starts_with("\"") {
+            // Errors within a string, such as a bad escape sequence, discard
+            // the whole string
+            self.advance_pos();
+            self.skip_string_rest();
+        } else {
+            while !self.is_done() && self.offset < why.span().stop.offset {
+                self.advance_pos();
+            }
+        }
 
-        while let Some(token) = self.next_token()? {
-            out.push(token);
+        if self.offset == start {
+            self.advance_pos();
         }
+    }
+
+    /// Skips the rest of a malformed string whose opening quote has been
+    /// read. Interpolations are skipped as a whole, so that braces and
+    /// strings within them do not end the string early.
+    fn skip_string_rest(&mut self) {
+        while let Ok(ch) = self.next_char() {
+            match ch {
+                '"' => return,
+                '\\' => {
+                    self.advance_pos();
+                }
+                '{' if self.starts_with("{") => {
+                    self.advance_pos();
+                }
+                '{' => self.skip_interpolation_rest(),
+                _ => (),
+            }
+        }
+    }
+
+    /// Skips the rest of an interpolation whose opening brace has been read,
+    /// lexing the tokens within it and skipping any malformed ones in turn.
+    fn skip_interpolation_rest(&mut self) {
+        let mut depth = 0;
+        loop {
+            // Line comments are rejected within interpolations, so they are
+            // skipped as stray symbols rather than running to the line end
+            self.skip_whitespace();
+            if self.starts_with("//") {
+                self.skip_chars(2);
+                continue;
+            }
+            if depth == 0 && self.starts_with("}") {
+                self.advance_pos();
+                return;
+            }
+
+            let start = self.offset;
+            match self.next_token() {
+                Ok(Some(token)) => match token.value {
+                    Token::OpenBrace => depth += 1,
+                    Token::CloseBrace => depth -= 1,
+                    _ => (),
+                },
+                Ok(None) => return,
+                Err(why) => {
+                    self.offset = start;
+                    self.skip_malformed(&why);
+                }
+            }
+        }
+    }
+}
 
-        Ok(out)
+impl Iterator for Lexer<'_> {
+    type Item = LexResult<SpanData<Token>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lookahead.pop_front().or_else(|| self.lex_next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::SourceMap;
+
+    fn lex(src: &str) -> Vec<Token> {
+        Lexer::new("<test>", src)
+            .try_parse_tokens()
+            .unwrap_or_else(|why| panic!("failed to lex {:?}: {}", src, why))
+            .into_iter()
+            .map(|token| token.value)
+            .collect()
+    }
+
+    fn ident(name: &str) -> Token {
+        Token::Identifier(name.into())
+    }
+
+    #[test]
+    fn number_literals() {
+        let cases = [
+            ("0", Token::Integer(0)),
+            ("123", Token::Integer(123)),
+            ("1_000_000", Token::Integer(1_000_000)),
+            ("0xFF", Token::Integer(0xFF)),
+            ("0xdead_beef", Token::Integer(0xdead_beef)),
+            ("0o17", Token::Integer(0o17)),
+            ("0b1010", Token::Integer(0b1010)),
+            ("1.5", Token::Float(1.5)),
+            ("1_000.000_1", Token::Float(1_000.000_1)),
+            ("1e-9", Token::Float(1e-9)),
+            ("1e+9", Token::Float(1e9)),
+            ("2.5E3", Token::Float(2.5e3)),
+            ("9223372036854775807", Token::Integer(i64::MAX)),
+            ("1.7976931348623157e308", Token::Float(f64::MAX)),
+            ("1e-400", Token::Float(0.0)),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(lex(src), vec![expected], "lexing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn invalid_number_literals() {
+        let cases = [
+            "0x",
+            "0b",
+            "0o8",
+            "0b102",
+            "0xFG",
+            "0x_1",
+            "1__0",
+            "1_",
+            "1e",
+            "1e+",
+            "1.5e_3",
+            "12ab",
+            "9223372036854775808",
+            "1e400",
+            "1.5e309",
+            "1_000e1_000",
+        ];
+        for src in cases {
+            let res = Lexer::new("<test>", src).try_parse_tokens();
+            assert!(
+                matches!(res, Err(LexError::InvalidNumber(_))),
+                "lexing {:?} gave {:?}",
+                src,
+                res
+            );
+        }
+    }
+
+    #[test]
+    fn literal_boundaries() {
+        let cases = [
+            (
+                "0..n",
+                vec![Token::Integer(0), Token::ExclusiveRange, ident("n")],
+            ),
+            (
+                "0..=9",
+                vec![Token::Integer(0), Token::InclusiveRange, Token::Integer(9)],
+            ),
+            (
+                "1..10",
+                vec![Token::Integer(1), Token::ExclusiveRange, Token::Integer(10)],
+            ),
+            (
+                "1.5..2.5",
+                vec![Token::Float(1.5), Token::ExclusiveRange, Token::Float(2.5)],
+            ),
+            (
+                "3.abs(x)",
+                vec![
+                    Token::Integer(3),
+                    Token::Period,
+                    ident("abs"),
+                    Token::OpenParen,
+                    ident("x"),
+                    Token::CloseParen,
+                ],
+            ),
+            (
+                "1.5.floor",
+                vec![Token::Float(1.5), Token::Period, ident("floor")],
+            ),
+            ("1.e5", vec![Token::Integer(1), Token::Period, ident("e5")]),
+            ("1.", vec![Token::Integer(1), Token::Period]),
+            ("x.0", vec![ident("x"), Token::Period, Token::Integer(0)]),
+            ("x1", vec![ident("x1")]),
+            ("-1", vec![Token::Minus, Token::Integer(1)]),
+            (
+                "[1]",
+                vec![Token::OpenBracket, Token::Integer(1), Token::CloseBracket],
+            ),
+            (
+                "\"a\"..\"b\"",
+                vec![
+                    Token::String("a".into()),
+                    Token::ExclusiveRange,
+                    Token::String("b".into()),
+                ],
+            ),
+            (
+                "True..None",
+                vec![Token::Boolean(true), Token::ExclusiveRange, Token::None],
+            ),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(lex(src), expected, "lexing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn symbol_boundaries() {
+        let symbols = [
+            (".", Token::Period),
+            (",", Token::Comma),
+            (";", Token::Semicolon),
+            (":", Token::Colon),
+            ("(", Token::OpenParen),
+            (")", Token::CloseParen),
+            ("[", Token::OpenBracket),
+            ("]", Token::CloseBracket),
+            ("{", Token::OpenBrace),
+            ("}", Token::CloseBrace),
+            ("=", Token::Equals),
+            ("+", Token::Plus),
+            ("+=", Token::PlusEquals),
+            ("-", Token::Minus),
+            ("-=", Token::MinusEquals),
+            ("*", Token::Times),
+            ("*=", Token::TimesEquals),
+            ("/", Token::Divide),
+            ("/=", Token::DivideEquals),
+            ("%", Token::Modulo),
+            ("%=", Token::ModuloEquals),
+            (">", Token::GreaterThan),
+            (">=", Token::GreaterThanEquals),
+            ("<", Token::LessThan),
+            ("<=", Token::LessThanEquals),
+            ("==", Token::DoubleEquals),
+            ("!=", Token::NotEquals),
+            ("!", Token::Not),
+            ("..", Token::ExclusiveRange),
+            ("..=", Token::InclusiveRange),
+            ("->", Token::SingleArrow),
+            ("=>", Token::DoubleArrow),
+            ("|", Token::Pipe),
+            ("?", Token::Question),
+        ];
+        for (symbol, token) in symbols {
+            assert_eq!(lex(symbol), vec![token.clone()], "lexing {:?}", symbol);
+
+            let src = format!("a{}b", symbol);
+            let expected = vec![ident("a"), token.clone(), ident("b")];
+            assert_eq!(lex(&src), expected, "lexing {:?}", src);
+
+            let src = format!("10{}x", symbol);
+            let expected = vec![Token::Integer(10), token, ident("x")];
+            assert_eq!(lex(&src), expected, "lexing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn adjacent_symbols() {
+        let cases = [
+            (
+                "foo();",
+                vec![
+                    ident("foo"),
+                    Token::OpenParen,
+                    Token::CloseParen,
+                    Token::Semicolon,
+                ],
+            ),
+            (
+                "f(-1)",
+                vec![
+                    ident("f"),
+                    Token::OpenParen,
+                    Token::Minus,
+                    Token::Integer(1),
+                    Token::CloseParen,
+                ],
+            ),
+            (
+                "a=-b",
+                vec![ident("a"), Token::Equals, Token::Minus, ident("b")],
+            ),
+            (
+                "x..=-y",
+                vec![ident("x"), Token::InclusiveRange, Token::Minus, ident("y")],
+            ),
+            ("!!=", vec![Token::Not, Token::NotEquals]),
+            (
+                "[[]];",
+                vec![
+                    Token::OpenBracket,
+                    Token::OpenBracket,
+                    Token::CloseBracket,
+                    Token::CloseBracket,
+                    Token::Semicolon,
+                ],
+            ),
+            ("=>=", vec![Token::DoubleArrow, Token::Equals]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(lex(src), expected, "lexing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn error_recovery() {
+        let cases = [
+            (
+                "let x = @ 1;",
+                vec![
+                    Token::Let,
+                    ident("x"),
+                    Token::Equals,
+                    Token::Error,
+                    Token::Integer(1),
+                    Token::Semicolon,
+                ],
+                1,
+            ),
+            (
+                "a $$b 0xZZ + c",
+                vec![
+                    ident("a"),
+                    Token::Error,
+                    ident("b"),
+                    Token::Error,
+                    Token::Plus,
+                    ident("c"),
+                ],
+                2,
+            ),
+            (
+                r#"f("\q", x)"#,
+                vec![
+                    ident("f"),
+                    Token::OpenParen,
+                    Token::Error,
+                    Token::Comma,
+                    ident("x"),
+                    Token::CloseParen,
+                ],
+                1,
+            ),
+            ("x \"open", vec![ident("x"), Token::Error], 1),
+            (
+                r#"x "{f("a") @}" y"#,
+                vec![ident("x"), Token::Error, ident("y")],
+                1,
+            ),
+            (
+                r#"x "{ {"}}"} "\q" }" y"#,
+                vec![ident("x"), Token::Error, ident("y")],
+                1,
+            ),
+            (
+                r#"x "{a // b}" y"#,
+                vec![ident("x"), Token::Error, ident("y")],
+                1,
+            ),
+            ("x /* open", vec![ident("x")], 1),
+        ];
+        for (src, expected, error_count) in cases {
+            let (tokens, errors) = Lexer::new("<test>", src).parse_tokens_recovering();
+            let tokens: Vec<_> = tokens.into_iter().map(|token| token.value).collect();
+            assert_eq!(tokens, expected, "lexing {:?}", src);
+            assert_eq!(errors.len(), error_count, "lexing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn error_spans() {
+        let cases = [
+            ("a @ b", "@", (0, 2)),
+            ("x = $$;", "$$", (0, 4)),
+            ("a\n  @", "@", (1, 2)),
+            ("let n = 0x;", "0x", (0, 8)),
+            ("1 + 12ab", "12ab", (0, 4)),
+            ("f(\"\\q\")", "\\q", (0, 3)),
+        ];
+        for (src, text, start) in cases {
+            let why = Lexer::new("<test>", src).try_parse_tokens().unwrap_err();
+            assert_eq!(&src[why.span().range()], text, "lexing {:?}", src);
+            assert_eq!(why.span().start.as_tuple(), start, "lexing {:?}", src);
+        }
+
+        match Lexer::new("<test>", "a $$ b").try_parse_tokens() {
+            Err(LexError::UnknownSymbol { symbol, .. }) => assert_eq!(symbol.as_ref(), "$$"),
+            res => panic!("lexing gave {:?}", res),
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let cases = [
+            ("a // b c\nd", vec![ident("a"), ident("d")]),
+            ("a //", vec![ident("a")]),
+            ("a / b", vec![ident("a"), Token::Divide, ident("b")]),
+            ("a /* b */ c", vec![ident("a"), ident("c")]),
+            ("a /* /* b */ c */ d", vec![ident("a"), ident("d")]),
+            ("a /* // */ b", vec![ident("a"), ident("b")]),
+            ("a /*\n * b\n */ c", vec![ident("a"), ident("c")]),
+            ("a /**/ b", vec![ident("a"), ident("b")]),
+            (
+                "a */ b",
+                vec![ident("a"), Token::Times, Token::Divide, ident("b")],
+            ),
+            (
+                "/// Adds one.\nfn",
+                vec![Token::DocComment("Adds one.".into()), Token::Function],
+            ),
+            (
+                "///no space\r\n///",
+                vec![
+                    Token::DocComment("no space".into()),
+                    Token::DocComment("".into()),
+                ],
+            ),
+            ("//// not a doc comment\nfn", vec![Token::Function]),
+            ("a // /// b\nc", vec![ident("a"), ident("c")]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(lex(src), expected, "lexing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn unterminated_comments() {
+        let cases = [
+            ("a /* b", (0, 2)),
+            ("a\n  /* /* b */", (1, 2)),
+            ("/* a */ /* /* */", (0, 8)),
+        ];
+        for (src, start) in cases {
+            match Lexer::new("<test>", src).try_parse_tokens() {
+                Err(LexError::UnterminatedComment(span)) => {
+                    assert_eq!(span.start.as_tuple(), start, "lexing {:?}", src);
+                    assert_eq!(&src[span.range()], "/*", "lexing {:?}", src);
+                }
+                res => panic!("lexing {:?} gave {:?}", src, res),
+            }
+        }
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let cases = [
+            (r#""\n \t \r \0 \\ \"""#, "\n \t \r \0 \\ \""),
+            (r#""\u{1F600}""#, "\u{1F600}"),
+            (r#""\u{41}\u{00e9}""#, "Aé"),
+            (r#""\u{10FFFF}""#, "\u{10FFFF}"),
+            (r#""a\\nb""#, "a\\nb"),
+            (r#""""#, ""),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(
+                lex(src),
+                vec![Token::String(expected.into())],
+                "lexing {:?}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_escape_sequences() {
+        let cases = [
+            (r#""\q""#, r"\q"),
+            (r#""a\u{D800}b""#, r"\u{D800}"),
+            (r#""\u{}""#, r"\u{}"),
+            (r#""\u{1234567}""#, r"\u{1234567}"),
+            (r#""\u{110000}""#, r"\u{110000}"),
+            (r#""\u1234""#, r"\u"),
+            (r#""\u{12""#, r"\u{12"),
+        ];
+        for (src, escape) in cases {
+            match Lexer::new("<test>", src).try_parse_tokens() {
+                Err(LexError::InvalidEscape(span)) => {
+                    assert_eq!(&src[span.range()], escape, "lexing {:?}", src)
+                }
+                res => panic!("lexing {:?} gave {:?}", src, res),
+            }
+        }
+    }
+
+    #[test]
+    fn unterminated_strings() {
+        let cases = [
+            ("\"abc", (0, 0)),
+            ("x = \"abc\\", (0, 4)),
+            ("f(\n  \"a\\\"b", (1, 2)),
+            ("\"a\" + \"b", (0, 6)),
+        ];
+        for (src, start) in cases {
+            match Lexer::new("<test>", src).try_parse_tokens() {
+                Err(LexError::UnterminatedString(span)) => {
+                    assert_eq!(span.start.as_tuple(), start, "lexing {:?}", src);
+                    assert_eq!(&src[span.range()], "\"", "lexing {:?}", src);
+                }
+                res => panic!("lexing {:?} gave {:?}", src, res),
+            }
+        }
+    }
+
+    /// Lexes a single interpolated string, showing literal segments as
+    /// strings and interpolations by the source text of their tokens.
+    fn segments(src: &str) -> Vec<String> {
+        match lex(src).as_slice() {
+            [Token::InterpolatedString(segments)] => segments
+                .iter()
+                .map(|segment| match segment {
+                    StringSegment::Literal(text) => format!("{:?}", text),
+                    StringSegment::Interpolation(tokens) => {
+                        let text: Vec<_> = tokens
+                            .value
+                            .iter()
+                            .map(|token| &src[token.span.range()])
+                            .collect();
+                        let braces = &src[tokens.span.range()];
+                        assert!(braces.starts_with('{') && braces.ends_with('}'));
+                        format!("{{{}}}", text.join(" "))
+                    }
+                })
+                .collect(),
+            tokens => panic!("lexing {:?} gave {:?}", src, tokens),
+        }
+    }
+
+    #[test]
+    fn interpolated_strings() {
+        let cases: [(&str, &[&str]); 6] = [
+            (
+                r#""Hello, {name}! You have {count + 1} items""#,
+                &[
+                    r#""Hello, ""#,
+                    "{name}",
+                    r#""! You have ""#,
+                    "{count + 1}",
+                    r#"" items""#,
+                ],
+            ),
+            (r#""{a}{b}""#, &["{a}", "{b}"]),
+            (r#""{{{x}}}""#, &[r#""{""#, "{x}", r#""}""#]),
+            (r#""{f("a}}")}""#, &[r#"{f ( "a}}" )}"#]),
+            (r#""{ {a: 1}.a }""#, &["{{ a : 1 } . a}"]),
+            (r#""{a /* } */}""#, &["{a}"]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(segments(src), expected, "lexing {:?}", src);
+        }
+
+        let cases = [
+            (r#""{{}}""#, "{}"),
+            (r#""a {{b}} c""#, "a {b} c"),
+            (r#""}}{{""#, "}{"),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(
+                lex(src),
+                vec![Token::String(expected.into())],
+                "lexing {:?}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_interpolations() {
+        let cases = [
+            (r#""{" x"#, (0, 0)),
+            (r#"x = "{a"#, (0, 4)),
+            (r#""{f("a)"#, (0, 0)),
+            (r#""{a}" + "{"#, (0, 8)),
+        ];
+        for (src, start) in cases {
+            match Lexer::new("<test>", src).try_parse_tokens() {
+                Err(LexError::UnterminatedString(span)) => {
+                    assert_eq!(span.start.as_tuple(), start, "lexing {:?}", src)
+                }
+                res => panic!("lexing {:?} gave {:?}", src, res),
+            }
+        }
+
+        for src in [r#""{x // c}""#, r#""{x /// c}""#, "\"{x //\n}\""] {
+            let why = Lexer::new("<test>", src).try_parse_tokens().unwrap_err();
+            assert_eq!(why.span().range(), 4..6, "lexing {:?}", src);
+            assert!(
+                why.to_string().contains("line comments are not allowed"),
+                "lexing {:?}",
+                src
+            );
+        }
+        assert_eq!(lex(r#""{x /* c */}""#).len(), 1);
+
+        for (src, brace) in [(r#""a}b""#, 2), (r#""{x}}""#, 4)] {
+            let why = Lexer::new("<test>", src).try_parse_tokens().unwrap_err();
+            assert_eq!(why.span().range(), brace..brace + 1, "lexing {:?}", src);
+            assert!(
+                why.to_string().contains("unmatched `}`"),
+                "lexing {:?}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn raw_strings() {
+        let cases = [
+            (r#"r"a\nb""#, r"a\nb"),
+            (r#"r"C:\path\""#, r"C:\path\"),
+            (r##"r#"say "hi""#"##, r#"say "hi""#),
+            (r###"r##"a "# b"##"###, r##"a "# b"##),
+            (r#"r"{x}""#, "{x}"),
+            ("r\"\"", ""),
+            ("r\"a\nb\"", "a\nb"),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(
+                lex(src),
+                vec![Token::String(expected.into())],
+                "lexing {:?}",
+                src
+            );
+        }
+        assert_eq!(lex("r + r1"), vec![ident("r"), Token::Plus, ident("r1")]);
+        let why = Lexer::new("<test>", "r##x").try_parse_tokens().unwrap_err();
+        assert_eq!(why.span().range(), 0..3);
+
+        for (src, start) in [(r##"r#"a"##, (0, 0)), (r###"x r##"a"#"###, (0, 2))] {
+            match Lexer::new("<test>", src).try_parse_tokens() {
+                Err(LexError::UnterminatedString(span)) => {
+                    assert_eq!(span.start.as_tuple(), start, "lexing {:?}", src)
+                }
+                res => panic!("lexing {:?} gave {:?}", src, res),
+            }
+        }
+    }
+
+    #[test]
+    fn multiline_strings() {
+        let cases = [
+            ("\"\"\"abc\"\"\"", "abc"),
+            ("\"\"\"\n    a\n      b\n    c\n    \"\"\"", "a\n  b\nc"),
+            ("\"\"\"\n\ta\n\n\tb\n\"\"\"", "a\n\nb"),
+            ("\"\"\"  a\n  b\"\"\"", "a\nb"),
+            ("\"\"\"\n    a\\n\\tb\n    \\u{41}\n\"\"\"", "a\n\tb\nA"),
+            ("\"\"\"\n    \\t a\n    b\n\"\"\"", "\t a\nb"),
+            ("\"\"\"say \"hi\"\"\"", "say \"hi"),
+            ("\"\"\"a \\\"\"\" b\"\"\"", "a \"\"\" b"),
+            ("\"\"\"{x}\"\"\"", "{x}"),
+            ("\"\"\"\r\n    a\r\n    b\r\n    \"\"\"", "a\nb"),
+            ("\"\"\"\r\n  a\r\n\r\n  b\"\"\"", "a\n\nb"),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(
+                lex(src),
+                vec![Token::String(expected.into())],
+                "lexing {:?}",
+                src
+            );
+        }
+
+        let src = "x = \"\"\"\n  a\n  \"\"\" + 1";
+        let tokens = Lexer::new("<test>", src).try_parse_tokens().unwrap();
+        assert_eq!(tokens[2].span.start.as_tuple(), (0, 4));
+        assert_eq!(tokens[2].span.stop.as_tuple(), (2, 5));
+        assert_eq!(tokens[3].value, Token::Plus);
+
+        let src = "\"\"\"\n  a\n  \\q\n\"\"\"";
+        match Lexer::new("<test>", src).try_parse_tokens() {
+            Err(LexError::InvalidEscape(span)) => {
+                assert_eq!(span.start.as_tuple(), (2, 2));
+                assert_eq!(&src[span.range()], "\\q");
+            }
+            res => panic!("lexing gave {:?}", res),
+        }
+        match Lexer::new("<test>", "\"\"\"a\\\"\"\"").try_parse_tokens() {
+            Err(LexError::UnterminatedString(span)) => assert_eq!(span.range(), 0..3),
+            res => panic!("lexing gave {:?}", res),
+        }
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let cases = [
+            ("café", vec![ident("café")]),
+            (
+                "变量 = 1",
+                vec![ident("变量"), Token::Equals, Token::Integer(1)],
+            ),
+            ("_ñ1 ünïcødé", vec![ident("_ñ1"), ident("ünïcødé")]),
+            ("Δx.λ", vec![ident("Δx"), Token::Period, ident("λ")]),
+            ("let ǅ", vec![Token::Let, ident("ǅ")]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(lex(src), expected, "lexing {:?}", src);
+        }
+        assert!(matches!(
+            Lexer::new("<test>", "a → b").try_parse_tokens(),
+            Err(LexError::UnknownSymbol { .. })
+        ));
+    }
+
+    #[test]
+    fn multi_byte_positions() {
+        let src = "café = \"ü\";\n  变量 + 😀x";
+        let (tokens, _) = Lexer::new("<test>", src).parse_tokens_recovering();
+        let positions: Vec<_> = tokens
+            .iter()
+            .map(|token| {
+                let (start, stop) = (&token.span.start, &token.span.stop);
+                (start.row, start.col, start.offset, stop.col, stop.offset)
+            })
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (0, 0, 0, 4, 5),
+                (0, 5, 6, 6, 7),
+                (0, 7, 8, 10, 12),
+                (0, 10, 12, 11, 13),
+                (1, 2, 16, 4, 22),
+                (1, 5, 23, 6, 24),
+                (1, 7, 25, 8, 29),
+                (1, 8, 29, 9, 30),
+            ]
+        );
+        assert_eq!(&src[tokens[4].span.range()], "变量");
+    }
+
+    #[test]
+    fn lookahead() {
+        let mut lexer = Lexer::new("<test>", "a + b");
+        let value = |token: Option<&LexResult<SpanData<Token>>>| {
+            token.map(|token| token.as_ref().unwrap().value.clone())
+        };
+        assert_eq!(value(lexer.peek_nth(2)), Some(ident("b")));
+        assert_eq!(value(lexer.peek()), Some(ident("a")));
+        assert_eq!(value(lexer.peek_nth(3)), None);
+        assert_eq!(lexer.next().unwrap().unwrap().value, ident("a"));
+        assert_eq!(value(lexer.peek()), Some(Token::Plus));
+        assert_eq!(value(lexer.peek_nth(1)), Some(ident("b")));
+        let rest: Vec<_> = lexer.map(|token| token.unwrap().value).collect();
+        assert_eq!(rest, vec![Token::Plus, ident("b")]);
+    }
+
+    #[test]
+    fn streaming_stops_after_error() {
+        let mut lexer = Lexer::new("<test>", "a @ b");
+        assert!(matches!(
+            lexer.peek_nth(1),
+            Some(Err(LexError::UnknownSymbol { .. }))
+        ));
+        assert!(lexer.peek_nth(2).is_none());
+
+        let tokens: Vec<_> = lexer.collect();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].as_ref().unwrap().value, ident("a"));
+        assert!(tokens[1].is_err());
+    }
+
+    #[test]
+    fn positions_match_line_index() {
+        let line = "let s = \"é{x}\" + r\"ü\" + f(1, 2.5) /* ß */ ; @ ";
+        let src = format!(
+            "{}\n\n{}\n\"\"\"\n  多\n\"\"\" {}",
+            line.repeat(20),
+            line,
+            line
+        );
+        let mut sources = SourceMap::new();
+        let file = sources.add("<test>", src.as_str());
+        let file = sources.get(file);
+
+        let (tokens, errors) = Lexer::for_file(file).parse_tokens_recovering();
+        assert_eq!(errors.len(), 22);
+        for token in &tokens {
+            for point in [&token.span.start, &token.span.stop] {
+                assert_eq!(Some(point), file.line_col(point.offset).as_ref());
+            }
+        }
     }
 }
@@ -2,7 +2,12 @@ mod lexer;
 mod prefix;
 mod types;
 mod ast;
+mod cst;
+mod source;
 
+pub use ast::*;
+pub use cst::*;
 pub use lexer::*;
 pub use prefix::*;
+pub use source::*;
 pub use types::*;
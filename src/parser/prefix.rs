@@ -26,12 +26,22 @@ impl<T> PrefixNode<T> {
             None => Some(self),
             Some(first_char) => {
                 let child = self.children.get(&first_char)?;
-                let slice = &prefix[1..];
+                let slice = &prefix[first_char.len_utf8()..];
                 child.find(slice)
             }
         }
     }
 
+    fn find_mut(&mut self, prefix: &str) -> Option<&mut PrefixNode<T>> {
+        match prefix.chars().next() {
+            None => Some(self),
+            Some(first_char) => {
+                let child = self.children.get_mut(&first_char)?;
+                child.find_mut(&prefix[first_char.len_utf8()..])
+            }
+        }
+    }
+
     fn find_or_create(&mut self, prefix: &str) -> &mut PrefixNode<T> {
         match prefix.chars().next() {
             None => self,
@@ -40,7 +50,23 @@ impl<T> PrefixNode<T> {
                     .children
                     .entry(first_char)
                     .or_insert_with(|| PrefixNode::new(None));
-                child_ref.find_or_create(&prefix[1..])
+                child_ref.find_or_create(&prefix[first_char.len_utf8()..])
+            }
+        }
+    }
+
+    /// Removes the value stored under `key`, pruning any nodes which are left
+    /// without a value or children.
+    fn remove(&mut self, key: &str) -> Option<T> {
+        match key.chars().next() {
+            None => self.value.take(),
+            Some(first_char) => {
+                let child = self.children.get_mut(&first_char)?;
+                let value = child.remove(&key[first_char.len_utf8()..]);
+                if child.value.is_none() && child.children.is_empty() {
+                    self.children.remove(&first_char);
+                }
+                value
             }
         }
     }
@@ -51,23 +77,37 @@ impl<T> PrefixNode<T> {
             child.add_all_chars_to_set(set);
         }
     }
+
+    /// The children of this node, in descending order of their characters.
+    fn children_descending(&self) -> Vec<(&char, &PrefixNode<T>)> {
+        let mut children: Vec<_> = self.children.iter().collect();
+        children.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+        children
+    }
 }
 
 #[derive(Debug)]
 pub struct PrefixTree<T> {
     root: PrefixNode<T>,
+    len: usize,
 }
 
 impl<T> PrefixTree<T> {
     pub fn new() -> PrefixTree<T> {
         PrefixTree {
             root: PrefixNode::root(),
+            len: 0,
         }
     }
 
-    pub fn insert(&mut self, prefix: &str, value: T) {
+    /// Inserts `value` under `prefix`, returning the value it replaces.
+    pub fn insert(&mut self, prefix: &str, value: T) -> Option<T> {
         let node = self.root.find_or_create(prefix);
-        node.value = Some(value);
+        let old = node.value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
     }
 
     pub fn find(&self, prefix: &str) -> Option<&T> {
@@ -77,6 +117,66 @@ impl<T> PrefixTree<T> {
         })
     }
 
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut T> {
+        self.root.find_mut(key)?.value.as_mut()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Removes `key` from the tree, returning its value if it was present.
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let value = self.root.remove(key);
+        if value.is_some() {
+            self.len -= 1;
+        }
+        value
+    }
+
+    /// The number of keys in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Finds the longest key in the tree which is a prefix of `text`,
+    /// returning that portion of `text` along with the key's value.
+    pub fn longest_match<'t>(&self, text: &'t str) -> Option<(&'t str, &T)> {
+        let mut node = &self.root;
+        let mut longest = node.value.as_ref().map(|value| (0, value));
+        for (offset, ch) in text.char_indices() {
+            node = match node.children.get(&ch) {
+                Some(child) => child,
+                None => break,
+            };
+            if let Some(value) = &node.value {
+                longest = Some((offset + ch.len_utf8(), value));
+            }
+        }
+        longest.map(|(len, value)| (&text[..len], value))
+    }
+
+    /// Iterates over every key and value, with keys in ascending order.
+    pub fn iter(&self) -> PrefixTreeIter<'_, T> {
+        PrefixTreeIter {
+            stack: vec![(String::new(), &self.root)],
+        }
+    }
+
+    /// Iterates in ascending order over every key beginning with `prefix`,
+    /// including `prefix` itself if it is a key.
+    pub fn keys_with_prefix(&self, prefix: &str) -> impl Iterator<Item = String> + '_ {
+        let stack = match self.root.find(prefix) {
+            Some(node) => vec![(prefix.to_string(), node)],
+            None => Vec::new(),
+        };
+        PrefixTreeIter { stack }.map(|(key, _)| key)
+    }
+
     pub fn get_all_chars(&self) -> HashSet<char> {
         let mut set = HashSet::new();
         self.root.add_all_chars_to_set(&mut set);
@@ -84,12 +184,239 @@ impl<T> PrefixTree<T> {
     }
 }
 
+impl<T> Default for PrefixTree<T> {
+    fn default() -> PrefixTree<T> {
+        PrefixTree::new()
+    }
+}
+
+impl<'a, T> Extend<(&'a str, T)> for PrefixTree<T> {
+    fn extend<I: IntoIterator<Item = (&'a str, T)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
 impl<'a, T> FromIterator<(&'a str, T)> for PrefixTree<T> {
     fn from_iter<I: IntoIterator<Item = (&'a str, T)>>(iter: I) -> PrefixTree<T> {
         let mut tree = PrefixTree::new();
-        for (key, value) in iter.into_iter() {
-            tree.insert(key, value);
-        }
+        tree.extend(iter);
         tree
     }
 }
+
+/// Iterates over the entries of a `PrefixTree` in ascending order of key.
+pub struct PrefixTreeIter<'a, T> {
+    /// Nodes still to be visited, with the next node to visit at the end.
+    stack: Vec<(String, &'a PrefixNode<T>)>,
+}
+
+impl<'a, T> Iterator for PrefixTreeIter<'a, T> {
+    type Item = (String, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, node)) = self.stack.pop() {
+            for (ch, child) in node.children_descending() {
+                let mut child_key = key.clone();
+                child_key.push(*ch);
+                self.stack.push((child_key, child));
+            }
+            if let Some(value) = &node.value {
+                return Some((key, value));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PrefixTree<T> {
+    type Item = (String, &'a T);
+    type IntoIter = PrefixTreeIter<'a, T>;
+
+    fn into_iter(self) -> PrefixTreeIter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Iterates over the entries of a `PrefixTree` by value, in ascending order
+/// of key.
+pub struct PrefixTreeIntoIter<T> {
+    stack: Vec<(String, PrefixNode<T>)>,
+}
+
+impl<T> Iterator for PrefixTreeIntoIter<T> {
+    type Item = (String, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, node)) = self.stack.pop() {
+            let mut children: Vec<_> = node.children.into_iter().collect();
+            children.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+            for (ch, child) in children {
+                let mut child_key = key.clone();
+                child_key.push(ch);
+                self.stack.push((child_key, child));
+            }
+            if let Some(value) = node.value {
+                return Some((key, value));
+            }
+        }
+        None
+    }
+}
+
+impl<T> IntoIterator for PrefixTree<T> {
+    type Item = (String, T);
+    type IntoIter = PrefixTreeIntoIter<T>;
+
+    fn into_iter(self) -> PrefixTreeIntoIter<T> {
+        PrefixTreeIntoIter {
+            stack: vec![(String::new(), self.root)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(keys: &[&'static str]) -> PrefixTree<usize> {
+        keys.iter().enumerate().map(|(i, &key)| (key, i)).collect()
+    }
+
+    fn keys<T>(tree: &PrefixTree<T>) -> Vec<String> {
+        tree.iter().map(|(key, _)| key).collect()
+    }
+
+    #[test]
+    fn insert_and_find() {
+        let mut tree = tree(&["a", "ab", "abc", "b"]);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.find("ab"), Some(&1));
+        assert_eq!(tree.find("abcd"), None);
+        assert!(tree.contains_key("b"));
+        assert!(!tree.contains_key("c"));
+
+        // Intermediate nodes hold no value until inserted
+        tree.insert("xyz", 10);
+        assert!(!tree.contains_key("xy"));
+
+        assert_eq!(tree.insert("ab", 5), Some(1));
+        assert_eq!(tree.find("ab"), Some(&5));
+        assert_eq!(tree.len(), 5);
+    }
+
+    #[test]
+    fn empty_key() {
+        let mut tree = PrefixTree::new();
+        assert!(tree.is_empty());
+        assert!(!tree.contains_key(""));
+
+        tree.insert("", 0);
+        tree.insert("a", 1);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.find(""), Some(&0));
+        assert_eq!(keys(&tree), vec!["", "a"]);
+        assert_eq!(tree.longest_match("xyz"), Some(("", &0)));
+
+        assert_eq!(tree.remove(""), Some(0));
+        assert_eq!(tree.remove(""), None);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.longest_match("xyz"), None);
+    }
+
+    #[test]
+    fn unicode_keys() {
+        let tree = tree(&["日本", "日", "日本語", "café", "cafe", "🦀"]);
+        assert_eq!(tree.find("日本"), Some(&0));
+        assert_eq!(tree.find("本"), None);
+        assert_eq!(tree.find("🦀"), Some(&5));
+        assert_eq!(tree.longest_match("日本人"), Some(("日本", &0)));
+        assert_eq!(tree.longest_match("café au lait"), Some(("café", &3)));
+        assert_eq!(
+            tree.keys_with_prefix("日").collect::<Vec<_>>(),
+            vec!["日", "日本", "日本語"]
+        );
+        assert_eq!(
+            tree.get_all_chars(),
+            "日本語cafeé🦀".chars().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let mut tree = tree(&["a", "ab", "abc", "b"]);
+        assert_eq!(tree.remove("ab"), Some(1));
+        assert_eq!(tree.remove("ab"), None);
+        assert_eq!(tree.remove("zzz"), None);
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.find("abc"), Some(&2));
+
+        // Removing a leaf prunes the nodes which led only to it
+        assert_eq!(tree.remove("abc"), Some(2));
+        assert_eq!(
+            tree.get_all_chars(),
+            ['a', 'b'].into_iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(keys(&tree), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn sorted_iteration() {
+        let tree = tree(&["pear", "apple", "peach", "pea", "banana", "app"]);
+        assert_eq!(
+            keys(&tree),
+            vec!["app", "apple", "banana", "pea", "peach", "pear"]
+        );
+        let values: Vec<_> = tree.iter().map(|(_, &value)| value).collect();
+        assert_eq!(values, vec![5, 1, 4, 3, 2, 0]);
+
+        let owned: Vec<_> = tree.into_iter().collect();
+        assert_eq!(
+            owned,
+            vec![
+                ("app".to_string(), 5),
+                ("apple".to_string(), 1),
+                ("banana".to_string(), 4),
+                ("pea".to_string(), 3),
+                ("peach".to_string(), 2),
+                ("pear".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_with_prefix() {
+        let tree = tree(&["let", "lets", "letter", "loop", "l"]);
+        let cases: [(&str, &[&str]); 5] = [
+            ("", &["l", "let", "lets", "letter", "loop"]),
+            ("l", &["l", "let", "lets", "letter", "loop"]),
+            ("let", &["let", "lets", "letter"]),
+            ("lett", &["letter"]),
+            ("x", &[]),
+        ];
+        for (prefix, expected) in cases {
+            let found: Vec<_> = tree.keys_with_prefix(prefix).collect();
+            assert_eq!(found, expected, "keys with prefix {:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut tree = tree(&["a", "ab"]);
+        *tree.get_mut("ab").unwrap() += 10;
+        assert_eq!(tree.find("ab"), Some(&11));
+        assert!(tree.get_mut("abc").is_none());
+        assert!(tree.get_mut("").is_none());
+    }
+
+    #[test]
+    fn extend() {
+        let mut tree = tree(&["a"]);
+        tree.extend([("b", 1), ("a", 2)]);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.find("a"), Some(&2));
+        let entries: Vec<_> = (&tree).into_iter().collect();
+        assert_eq!(entries, vec![("a".to_string(), &2), ("b".to_string(), &1)]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{fs, io, ops::Range, path::Path};
+
+use crate::parser::{Point, Span, Str};
+
+/// Identifies a file within a `SourceMap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(u32);
+
+/// The byte offsets at which each line of a source begins, used to convert
+/// byte offsets into rows and columns.
+#[derive(Clone, Debug)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(src: &str) -> LineIndex {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        LineIndex {
+            line_starts,
+            len: src.len(),
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The row containing the byte offset `offset`.
+    pub fn row(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// The row containing `offset`, checking the row `hint` before searching
+    /// every line.
+    pub fn row_near(&self, offset: usize, hint: usize) -> usize {
+        match self.line_range(hint) {
+            Some(range) if range.start <= offset && offset < range.end => hint,
+            _ => self.row(offset),
+        }
+    }
+
+    /// The byte range of the line at `row`, including its line break.
+    pub fn line_range(&self, row: usize) -> Option<Range<usize>> {
+        let start = *self.line_starts.get(row)?;
+        let end = self.line_starts.get(row + 1).copied().unwrap_or(self.len);
+        Some(start..end)
+    }
+
+    /// Converts a byte offset into `src`, which must be the source this
+    /// index was built from, to a `Point`.
+    pub fn point(&self, src: &str, offset: usize, row: usize) -> Point {
+        let col = src[self.line_starts[row]..offset].chars().count();
+        Point { row, col, offset }
+    }
+}
+
+/// A source file owned by a `SourceMap`.
+#[derive(Debug)]
+pub struct SourceFile {
+    id: FileId,
+    name: Str,
+    src: String,
+    lines: LineIndex,
+}
+
+impl SourceFile {
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    pub fn name(&self) -> &Str {
+        &self.name
+    }
+
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    pub fn lines(&self) -> &LineIndex {
+        &self.lines
+    }
+
+    /// Converts a byte offset into the file to a row and column, or `None` if
+    /// it is out of bounds or not on a character boundary.
+    pub fn line_col(&self, offset: usize) -> Option<Point> {
+        if !self.src.is_char_boundary(offset) {
+            return None;
+        }
+        let row = self.lines.row(offset);
+        Some(self.lines.point(&self.src, offset, row))
+    }
+
+    /// The text of the line at `row`, without its line break.
+    pub fn line_text(&self, row: usize) -> Option<&str> {
+        let line = &self.src[self.lines.line_range(row)?];
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        Some(line.strip_suffix('\r').unwrap_or(line))
+    }
+
+    /// The source text covered by `span`.
+    pub fn snippet(&self, span: &Span) -> Option<&str> {
+        self.src.get(span.range())
+    }
+}
+
+/// Owns the source of every file in a program, so that spans can be
+/// resolved back to their text.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { files: Vec::new() }
+    }
+
+    pub fn add(&mut self, name: impl Into<Str>, src: impl Into<String>) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        let src = src.into();
+        self.files.push(SourceFile {
+            id,
+            name: name.into(),
+            lines: LineIndex::new(&src),
+            src,
+        });
+        id
+    }
+
+    /// Reads a file from disk, naming it by its path.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<FileId> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+        Ok(self.add(path.display().to_string(), src))
+    }
+
+    pub fn get(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &SourceFile> {
+        self.files.iter()
+    }
+
+    /// The file a span was lexed from, if it belongs to this map.
+    pub fn file_of(&self, span: &Span) -> Option<&SourceFile> {
+        self.files.get(span.file?.0 as usize)
+    }
+
+    pub fn line_col(&self, file: FileId, offset: usize) -> Option<Point> {
+        self.get(file).line_col(offset)
+    }
+
+    pub fn line_text(&self, file: FileId, row: usize) -> Option<&str> {
+        self.get(file).line_text(row)
+    }
+
+    /// The source text covered by `span`.
+    pub fn snippet(&self, span: &Span) -> Option<&str> {
+        self.file_of(span)?.snippet(span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Lexer;
+
+    #[test]
+    fn line_col() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("a", "ab\r\nçd\n\nx");
+        let cases = [
+            (0, (0, 0)),
+            (2, (0, 2)),
+            (4, (1, 0)),
+            (6, (1, 1)),
+            (8, (2, 0)),
+            (9, (3, 0)),
+            (10, (3, 1)),
+        ];
+        for (offset, expected) in cases {
+            let point = sources.line_col(file, offset).unwrap();
+            assert_eq!(point.as_tuple(), expected, "offset {}", offset);
+        }
+        assert!(sources.line_col(file, 5).is_none());
+        assert!(sources.line_col(file, 11).is_none());
+    }
+
+    #[test]
+    fn line_text() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("a", "one\r\ntwo\n\nfour");
+        let lines: Vec<_> = (0..5).map(|row| sources.line_text(file, row)).collect();
+        assert_eq!(
+            lines,
+            vec![Some("one"), Some("two"), Some(""), Some("four"), None]
+        );
+    }
+
+    #[test]
+    fn snippets_across_files() {
+        let mut sources = SourceMap::new();
+        let first = sources.add("first", "let a = 1;");
+        let second = sources.add("second", "let bb = 22;");
+        assert_ne!(first, second);
+
+        let tokens = Lexer::for_file(sources.get(second))
+            .try_parse_tokens()
+            .unwrap();
+        let snippets: Vec<_> = tokens
+            .iter()
+            .map(|token| sources.snippet(&token.span).unwrap())
+            .collect();
+        assert_eq!(snippets, vec!["let", "bb", "=", "22", ";"]);
+        assert_eq!(
+            sources.file_of(&tokens[1].span).unwrap().name().as_ref(),
+            "second"
+        );
+
+        let span = tokens[1].span.to(&tokens[3].span);
+        assert_eq!(sources.snippet(&span), Some("bb = 22"));
+
+        // Spans from a lexer without a file cannot be resolved
+        let tokens = Lexer::new("loose", "x").try_parse_tokens().unwrap();
+        assert_eq!(sources.snippet(&tokens[0].span), None);
+    }
+}
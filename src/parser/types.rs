@@ -1,43 +1,43 @@
+use std::fmt;
+
+use crate::parser::{FileId, LexError, Token};
+
 pub type Str = std::sync::Arc<str>;
 
-#[derive(Debug, Clone)]
+/// A position in source text. `row` and `col` count lines and characters from
+/// zero, while `offset` is the byte offset from the start of the source file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Point {
     pub row: usize,
     pub col: usize,
+    pub offset: usize,
 }
 
 impl Point {
-    pub fn increment_col(&mut self) {
-        self.col += 1;
-    }
-
-    pub fn increment_row(&mut self) {
-        self.col = 0;
-        self.row += 1;
-    }
-
     pub fn as_tuple(&self) -> (usize, usize) {
         (self.row, self.col)
     }
 }
 
-impl From<(usize, usize)> for Point {
-    fn from((row, col): (usize, usize)) -> Point {
-        Point { row, col }
-    }
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Span {
     pub name: Str,
+    /// The file in a `SourceMap` which the span belongs to, if any.
+    pub file: Option<FileId>,
     pub start: Point,
     pub stop: Point,
 }
 
 impl Span {
+    /// The byte range covered by the span.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start.offset..self.stop.offset
+    }
+
     pub fn from(&self, other: &Span) -> Span {
         Span {
             name: self.name.clone(),
+            file: self.file,
             start: other.start.clone(),
             stop: self.stop.clone(),
         }
@@ -46,22 +46,56 @@ impl Span {
     pub fn to(&self, other: &Span) -> Span {
         Span {
             name: self.name.clone(),
+            file: self.file,
             start: self.start.clone(),
             stop: other.stop.clone(),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.name,
+            self.start.row + 1,
+            self.start.col + 1
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct SpanData<T> {
     pub span: Span,
     pub value: T,
 }
 
+/// A named type applied to type arguments, such as `Map<String, Number>`.
+#[derive(Clone, Debug)]
+pub struct GenericType {
+    pub name: SpanData<Str>,
+    pub args: Vec<SpanData<TypeExpression>>,
+}
+
+/// The type of a function, such as `fn(Number, String) -> Bool`.
+#[derive(Clone, Debug)]
+pub struct FunctionType {
+    pub args: Vec<SpanData<TypeExpression>>,
+    pub return_type: Option<Box<SpanData<TypeExpression>>>,
+}
+
+#[derive(Clone, Debug)]
 pub enum TypeExpression {
     Identifier(Str),
+    Generic(GenericType),
+    Optional(Box<SpanData<TypeExpression>>),
+    Union(Vec<SpanData<TypeExpression>>),
+    Tuple(Vec<SpanData<TypeExpression>>),
+    Function(FunctionType),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BinaryOperator {
     Plus,
     Minus,
@@ -76,48 +110,206 @@ pub enum BinaryOperator {
     GreaterThanEquals,
 }
 
+impl BinaryOperator {
+    pub fn from_token(token: &Token) -> Option<BinaryOperator> {
+        let operator = match token {
+            Token::Plus => BinaryOperator::Plus,
+            Token::Minus => BinaryOperator::Minus,
+            Token::Times => BinaryOperator::Times,
+            Token::Divide => BinaryOperator::Divide,
+            Token::Modulo => BinaryOperator::Modulo,
+            Token::DoubleEquals => BinaryOperator::Equals,
+            Token::NotEquals => BinaryOperator::NotEquals,
+            Token::LessThan => BinaryOperator::LessThan,
+            Token::LessThanEquals => BinaryOperator::LessThanEquals,
+            Token::GreaterThan => BinaryOperator::GreaterThan,
+            Token::GreaterThanEquals => BinaryOperator::GreaterThanEquals,
+            _ => return None,
+        };
+        Some(operator)
+    }
+
+    /// Binding power of the operator. Higher values bind more tightly.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Equals | BinaryOperator::NotEquals => 1,
+            BinaryOperator::LessThan
+            | BinaryOperator::LessThanEquals
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanEquals => 2,
+            BinaryOperator::Plus | BinaryOperator::Minus => 3,
+            BinaryOperator::Times | BinaryOperator::Divide | BinaryOperator::Modulo => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UnaryOperator {
     Negative,
     Not,
 }
 
+impl UnaryOperator {
+    pub fn from_token(token: &Token) -> Option<UnaryOperator> {
+        match token {
+            Token::Minus => Some(UnaryOperator::Negative),
+            Token::Not => Some(UnaryOperator::Not),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Unary {
-    operator: UnaryOperator,
-    target: Box<Expression>,
+    pub operator: UnaryOperator,
+    pub target: Box<SpanData<Expression>>,
 }
 
+#[derive(Clone, Debug)]
 pub struct Binary {
-    operator: BinaryOperator,
-    lhs: Box<Expression>,
-    rhs: Box<Expression>,
+    pub operator: BinaryOperator,
+    pub lhs: Box<SpanData<Expression>>,
+    pub rhs: Box<SpanData<Expression>>,
 }
 
+#[derive(Clone, Debug)]
 pub struct Block {
-    body: Vec<SpanData<Statement>>,
-    value: Option<Box<Expression>>,
+    pub body: Vec<SpanData<Statement>>,
+    pub value: Option<Box<SpanData<Expression>>>,
 }
 
+/// An `if` expression. An `else if` chain is represented as an `otherwise`
+/// block whose value is the nested `If`.
+#[derive(Clone, Debug)]
 pub struct If {
-    condition: Box<Expression>,
-    then: Option<Block>,
-    otherwise: Option<Block>,
+    pub condition: Box<SpanData<Expression>>,
+    pub then: Block,
+    pub otherwise: Option<Block>,
+}
+
+/// An assignment such as `a = b`. Compound assignments such as `a += b`
+/// carry the binary operator that is applied before assigning.
+#[derive(Clone, Debug)]
+pub struct Assign {
+    pub operator: Option<BinaryOperator>,
+    pub target: Box<SpanData<Expression>>,
+    pub value: Box<SpanData<Expression>>,
+}
+
+/// A range such as `a..b` or `a..=b`.
+#[derive(Clone, Debug)]
+pub struct Range {
+    pub start: Box<SpanData<Expression>>,
+    pub stop: Box<SpanData<Expression>>,
+    pub inclusive: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub target: Box<SpanData<Expression>>,
+    pub args: Vec<SpanData<Expression>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Index {
+    pub target: Box<SpanData<Expression>>,
+    pub index: Box<SpanData<Expression>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub target: Box<SpanData<Expression>>,
+    pub member: SpanData<Str>,
+}
+
+/// A `key: value` pair in a map literal. Identifier keys are stored by name,
+/// so `{ a: 1 }` and `{ "a": 1 }` are equivalent.
+#[derive(Clone, Debug)]
+pub struct MapEntry {
+    pub key: SpanData<Str>,
+    pub value: SpanData<Expression>,
+}
+
+/// An anonymous function such as `fn(x, y) => x + y` or
+/// `(x: Number) -> Number => x * 2`.
+#[derive(Clone, Debug)]
+pub struct Lambda {
+    pub args: Vec<Typed<Str>>,
+    pub return_type: Option<SpanData<TypeExpression>>,
+    pub body: Block,
+}
+
+/// A piece of an interpolated string expression.
+#[derive(Clone, Debug)]
+pub enum InterpolationSegment {
+    Literal(Str),
+    Expression(Box<SpanData<Expression>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Loop {
+    pub body: Block,
 }
 
+#[derive(Clone, Debug)]
+pub struct While {
+    pub condition: Box<SpanData<Expression>>,
+    pub body: Block,
+}
+
+#[derive(Clone, Debug)]
+pub struct For {
+    pub binding: Str,
+    pub iterable: Box<SpanData<Expression>>,
+    pub body: Block,
+}
+
+#[derive(Clone, Debug)]
 pub enum Expression {
     None,
-    Number(f64),
+    Integer(i64),
+    Float(f64),
     Boolean(bool),
     String(Str),
+    Interpolated(Vec<InterpolationSegment>),
     Identifier(Str),
+    List(Vec<SpanData<Expression>>),
+    Map(Vec<MapEntry>),
+    Lambda(Lambda),
     Unary(Unary),
     Binary(Binary),
+    Call(Call),
+    Index(Index),
+    Member(Member),
+    Assign(Assign),
+    Range(Range),
+    Block(Block),
+    If(If),
+    Loop(Loop),
+    While(While),
+    For(For),
+    Break(Option<Box<SpanData<Expression>>>),
+    Continue,
+    Return(Option<Box<SpanData<Expression>>>),
 }
 
+impl Expression {
+    /// Whether the expression refers to a location that can be assigned to.
+    pub fn is_place(&self) -> bool {
+        matches!(
+            self,
+            Expression::Identifier(_) | Expression::Index(_) | Expression::Member(_)
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Typed<T> {
-    type_expr: Option<TypeExpression>,
-    value: T,
+    pub type_expr: Option<SpanData<TypeExpression>>,
+    pub value: T,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Visibility {
     Public,
     Private,
@@ -125,42 +317,125 @@ pub enum Visibility {
 
 impl Visibility {
     pub fn is_public(&self) -> bool {
-        match self {
-            Visibility::Public => true,
-            _ => false,
-        }
+        matches!(self, Visibility::Public)
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct Declaration {
-    visibility: Visibility,
-    name: Typed<Str>,
-    value: SpanData<Expression>,
+    pub visibility: Visibility,
+    pub name: Typed<Str>,
+    pub value: SpanData<Expression>,
 }
 
+#[derive(Clone, Debug)]
 pub struct Function {
-    visibility: Visibility,
-    name: Str,
-    args: Vec<Typed<Str>>,
-    body: Block,
+    pub visibility: Visibility,
+    pub name: Str,
+    pub args: Vec<Typed<Str>>,
+    pub return_type: Option<SpanData<TypeExpression>>,
+    pub body: Block,
 }
 
+#[derive(Clone, Debug)]
 pub enum Statement {
-    Declaration(Declaration),
+    Declaration(Box<Declaration>),
     Function(Function),
     Expression(SpanData<Expression>),
 }
 
+#[derive(Clone, Debug)]
 pub enum ParseError {
-    EOF,
-    ExpectedToken,
-    Custom(Str),
+    /// The tokens ran out while one of `expected` was required.
+    Eof {
+        span: Span,
+        expected: Vec<Str>,
+    },
+    /// The token `found` was encountered instead of one of `expected`.
+    ExpectedToken {
+        span: Span,
+        expected: Vec<Str>,
+        found: Token,
+    },
+    /// The left-hand side of an assignment is not an assignable place.
+    InvalidAssignment(Span),
+    /// The token stream ended early because the source could not be lexed.
+    Lex(LexError),
+    Custom {
+        span: Span,
+        message: Str,
+    },
 }
 
 impl ParseError {
-    pub fn custom(msg: impl Into<Str>) -> ParseError {
-        ParseError::Custom(msg.into())
+    pub fn eof(span: Span, expected: &[&str]) -> ParseError {
+        ParseError::Eof {
+            span,
+            expected: expected.iter().map(|&name| name.into()).collect(),
+        }
+    }
+
+    pub fn expected(found: SpanData<Token>, expected: &[&str]) -> ParseError {
+        ParseError::ExpectedToken {
+            span: found.span,
+            expected: expected.iter().map(|&name| name.into()).collect(),
+            found: found.value,
+        }
+    }
+
+    pub fn custom(span: Span, msg: impl Into<Str>) -> ParseError {
+        ParseError::Custom {
+            span,
+            message: msg.into(),
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            ParseError::Eof { span, .. }
+            | ParseError::ExpectedToken { span, .. }
+            | ParseError::InvalidAssignment(span)
+            | ParseError::Custom { span, .. } => span,
+            ParseError::Lex(why) => why.span(),
+        }
+    }
+}
+
+/// Formats a list of alternatives as `a`, `a or b` or `a, b or c`.
+pub(crate) fn format_alternatives(items: &[Str]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => item.to_string(),
+        [rest @ .., last] => format!("{} or {}", rest.join(", "), last),
     }
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Eof { expected, .. } if expected.is_empty() => {
+                write!(f, "unexpected end of input")
+            }
+            ParseError::Eof { expected, .. } => write!(
+                f,
+                "expected {}, found end of input",
+                format_alternatives(expected)
+            ),
+            ParseError::ExpectedToken {
+                expected, found, ..
+            } => write!(
+                f,
+                "expected {}, found {}",
+                format_alternatives(expected),
+                found
+            ),
+            ParseError::InvalidAssignment(_) => write!(f, "invalid left-hand side of assignment"),
+            ParseError::Lex(why) => write!(f, "{}", why),
+            ParseError::Custom { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 pub type ParseResult<T> = Result<T, ParseError>;